@@ -1,11 +1,12 @@
+use std::borrow::Cow;
 use std::sync::Arc;
 
 use log::{error, info};
 
 use tokio::sync::Mutex;
 
-use russh::keys::ssh_key::PublicKey;
-use russh::server::{Auth, Handler, Msg, Session};
+use russh::keys::PublicKey;
+use russh::server::{Auth, Handler, Msg, Response, Session};
 use russh::{Channel, ChannelId, MethodKind, MethodSet, Pty};
 
 use ratatui::layout::Rect;
@@ -18,7 +19,13 @@ pub struct App {
     server_controller: Arc<AppServerController>,
     pub app_controller: Option<Arc<Mutex<AppController>>>,
     pub decoder: Decoder,
+    /// Escape sequence the decoder passed through as separate keys, collected
+    /// until it is complete.
+    pub escape_sequence: Option<String>,
     user: User,
+    client_id: Option<usize>,
+    /// Verified key the client offered before falling back to password auth.
+    offered_key: Option<PublicKey>,
 }
 
 impl App {
@@ -27,7 +34,10 @@ impl App {
             server_controller,
             app_controller: None,
             decoder: Decoder::new(),
+            escape_sequence: None,
             user: User::unauthenticated(),
+            client_id: None,
+            offered_key: None,
         }
     }
 
@@ -46,9 +56,32 @@ impl App {
             )
             .await?,
         ));
+        controller.lock().await.session_key = self.offered_key.clone();
         self.app_controller = Some(Arc::clone(&controller));
         Ok(controller)
     }
+
+    pub async fn remove_client(&mut self) {
+        if let Some(client_id) = self.client_id.take() {
+            self.server_controller.remove_client(client_id).await;
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        let Some(client_id) = self.client_id.take() else {
+            return;
+        };
+        let server_controller = Arc::clone(&self.server_controller);
+        let app_controller = self.app_controller.clone();
+        tokio::spawn(async move {
+            if let Some(app_controller) = app_controller {
+                app_controller.lock().await.active = false;
+            }
+            server_controller.remove_client(client_id).await;
+        });
+    }
 }
 
 impl Handler for App {
@@ -64,16 +97,88 @@ impl Handler for App {
             .create_controller(session, channel.id(), self.user.clone())
             .await?;
         info!("Created app controller for {}", self.user.username);
-        self.server_controller.add_client(app_controller).await;
+        self.client_id = Some(self.server_controller.add_client(app_controller).await);
         info!("Added app controller to server controller for {}", self.user.username);
         Ok(true)
     }
 
-    async fn auth_publickey(&mut self, _: &str, _: &PublicKey) -> Result<Auth, Self::Error> {
-        Ok(Auth::Reject {
-            proceed_with_methods: Some(MethodSet::from(&[MethodKind::Password][..])),
-            partial_success: false,
-        })
+    async fn auth_publickey(&mut self, user: &str, key: &PublicKey) -> Result<Auth, Self::Error> {
+        let username = user;
+        info!("Authenticating {} using public key", username);
+        match self.server_controller.auth_user_key(username, key).await {
+            Ok(user) => {
+                info!("{} authenticated", user.username);
+                self.user = user;
+                Ok(Auth::Accept)
+            }
+            Err(e) => {
+                info!("Public key rejected for {}: {}", username, e);
+                self.offered_key = Some(key.clone());
+                Ok(Auth::Reject {
+                    proceed_with_methods: Some(MethodSet::from(
+                        &[MethodKind::KeyboardInteractive, MethodKind::Password][..],
+                    )),
+                    partial_success: false,
+                })
+            }
+        }
+    }
+
+    /// Only used to sign up with an invite code. Everyone else is sent on to
+    /// password authentication.
+    async fn auth_keyboard_interactive<'a>(
+        &'a mut self,
+        user: &str,
+        _submethods: &str,
+        response: Option<Response<'a>>,
+    ) -> Result<Auth, Self::Error> {
+        let username = user;
+        let Some(response) = response else {
+            return match self.server_controller.needs_invite(username).await {
+                Ok(true) => Ok(Auth::Partial {
+                    name: Cow::Borrowed("Sign up"),
+                    instructions: Cow::Owned(format!(
+                        "There is no account named {} yet. Enter your invite code and choose a password.",
+                        username
+                    )),
+                    prompts: Cow::Owned(vec![
+                        (Cow::Borrowed("Invite code: "), true),
+                        (Cow::Borrowed("Password: "), false),
+                    ]),
+                }),
+                Ok(false) => Ok(Auth::Reject {
+                    proceed_with_methods: Some(MethodSet::from(&[MethodKind::Password][..])),
+                    partial_success: false,
+                }),
+                Err(e) => {
+                    error!("Error checking invite for {}: {}", username, e);
+                    Ok(Auth::reject())
+                }
+            };
+        };
+
+        let answers: Vec<String> = response
+            .map(|answer| String::from_utf8_lossy(&answer).to_string())
+            .collect();
+        let [code, password] = answers.as_slice() else {
+            return Ok(Auth::reject());
+        };
+        info!("Signing up {} with an invite", username);
+        match self
+            .server_controller
+            .register_with_invite(username, code, password)
+            .await
+        {
+            Ok(user) => {
+                info!("{} authenticated", user.username);
+                self.user = user;
+                Ok(Auth::Accept)
+            }
+            Err(e) => {
+                error!("Error signing up {}: {}", username, e);
+                Ok(Auth::reject())
+            }
+        }
     }
 
     async fn auth_password(&mut self, user: &str, password: &str) -> Result<Auth, Self::Error> {
@@ -117,6 +222,7 @@ impl Handler for App {
         if let Some(controller) = &self.app_controller {
             let mut controller = controller.lock().await;
             controller.resize_terminal(rect);
+            controller.draw()?;
         }
         session.channel_success(channel)?;
         info!("PTY granted for {}", self.user.username);
@@ -143,6 +249,7 @@ impl Handler for App {
         if let Some(controller) = &self.app_controller {
             let mut controller = controller.lock().await;
             controller.resize_terminal(rect);
+            controller.draw()?;
         }
 
         Ok(())
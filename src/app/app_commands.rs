@@ -0,0 +1,669 @@
+use russh::keys::PublicKey;
+
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+
+use crate::{
+    app::{
+        AppController,
+        command_registry::{Command, CommandArgs, CommandFuture, CommandRegistry},
+        mentions,
+        presence::{self, presence_name},
+        reactions,
+    },
+    db_models::{Channel, MessageSearch, Presence},
+};
+
+/// The commands sshlack ships with, from every feature module. Add more to
+/// it before passing it to the server.
+pub fn builtin_commands() -> CommandRegistry {
+    let mut registry = CommandRegistry::default();
+    for register in [
+        register,
+        mentions::register,
+        presence::register,
+        reactions::register,
+    ] {
+        register(&mut registry);
+    }
+    registry
+}
+
+/// Adds the commands for channels, messages, accounts and help.
+pub fn register(registry: &mut CommandRegistry) {
+    let commands = [
+        Command {
+            name: "/join",
+            usage: "#channel",
+            description: "Join a channel, or switch to it",
+            handler: join,
+        },
+        Command {
+            name: "/create",
+            usage: "#channel",
+            description: "Create a channel and join it",
+            handler: create,
+        },
+        Command {
+            name: "/leave",
+            usage: "[#channel]",
+            description: "Leave the current or given channel",
+            handler: leave,
+        },
+        Command {
+            name: "/dm",
+            usage: "@user [@user...]",
+            description: "Open a direct conversation",
+            handler: dm,
+        },
+        Command {
+            name: "/me",
+            usage: "<action>",
+            description: "Send an action, e.g. /me waves",
+            handler: me,
+        },
+        Command {
+            name: "/nick",
+            usage: "[nickname]",
+            description: "Set the name others see, or clear it",
+            handler: nick,
+        },
+        Command {
+            name: "/topic",
+            usage: "[text | -]",
+            description: "Show the channel topic, set it, or clear it with -",
+            handler: topic,
+        },
+        Command {
+            name: "/who",
+            usage: "",
+            description: "List the members of the channel",
+            handler: who,
+        },
+        Command {
+            name: "/edit",
+            usage: "<new text>",
+            description: "Edit the selected message, or your last one",
+            handler: edit,
+        },
+        Command {
+            name: "/delete",
+            usage: "",
+            description: "Delete the selected message, or your last one",
+            handler: delete,
+        },
+        Command {
+            name: "/search",
+            usage: "[words] [from:@user] [in:#channel] [after:2024-05-01|7d] [before:...]",
+            description: "Search messages in your channels",
+            handler: search,
+        },
+        Command {
+            name: "/key",
+            usage: "add [ssh-ed25519 AAAA...] | list | revoke <id>",
+            description: "Manage the SSH keys you can log in with",
+            handler: key,
+        },
+        Command {
+            name: "/invite",
+            usage: "[24h|7d] | list | revoke <code>",
+            description: "Manage invite codes (admins)",
+            handler: invite,
+        },
+        Command {
+            name: "/user",
+            usage: "add <username> <password>",
+            description: "Create an account (admins)",
+            handler: user,
+        },
+        Command {
+            name: "/help",
+            usage: "[command]",
+            description: "List commands, or explain one",
+            handler: help,
+        },
+        Command {
+            name: "/quit",
+            usage: "",
+            description: "Disconnect",
+            handler: quit,
+        },
+    ];
+    for command in commands {
+        registry.register(command);
+    }
+}
+
+fn join<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [name] => controller.join_channel(name).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn create<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [name] => controller.create_channel(name).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn leave<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.leave_channel(None).await,
+            [name] => controller.leave_channel(Some(name)).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn dm<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        if args.words.is_empty() {
+            return Err(args.usage_error());
+        }
+        let recipients: Vec<String> = args
+            .words
+            .iter()
+            .map(|name| name.trim_start_matches('@').to_string())
+            .collect();
+        controller.open_direct_channel(&recipients).await
+    })
+}
+
+fn me<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        if args.text.is_empty() {
+            return Err(args.usage_error());
+        }
+        controller.send_message(format!("/me {}", args.text)).await
+    })
+}
+
+fn nick<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.set_nickname(None).await,
+            [nickname] => controller.set_nickname(Some(nickname)).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn topic<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.text {
+            "" => controller.show_topic(),
+            "-" => controller.set_topic(None).await,
+            text => controller.set_topic(Some(text)).await,
+        }
+    })
+}
+
+fn who<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.list_members().await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn edit<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        if args.text.is_empty() {
+            return Err(args.usage_error());
+        }
+        controller.edit_target_message(args.text.to_string()).await
+    })
+}
+
+fn delete<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.delete_target_message().await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn search<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        if args.words.is_empty() {
+            return Err(args.usage_error());
+        }
+        controller.search_command(&args.words).await
+    })
+}
+
+fn key<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            ["add"] => controller.add_session_key().await,
+            ["add", key @ ..] => controller.add_key(&key.join(" ")).await,
+            ["list"] => controller.list_keys().await,
+            ["revoke", id] => controller.revoke_key(id).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn invite<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.create_invite(None).await,
+            ["list"] => controller.list_invites().await,
+            ["revoke", code] => controller.revoke_invite(code).await,
+            [validity] => controller.create_invite(Some(validity)).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn user<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            ["add", username, password] => controller.add_account(username, password).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn help<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.list_commands(),
+            [name] => controller.explain_command(name),
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn quit<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => {
+                controller.disconnect().await;
+                Ok(())
+            }
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+impl AppController {
+    /// Runs a slash command. Input that isn't a registered command is never sent.
+    pub async fn run_command(&mut self, input: &str) -> Result<(), anyhow::Error> {
+        let server_controller = self.server_controller.clone();
+        let (command, args) = server_controller.commands.parse(input)?;
+        (command.handler)(self, args).await
+    }
+
+    fn list_commands(&mut self) -> Result<(), anyhow::Error> {
+        let server_controller = self.server_controller.clone();
+        for command in server_controller.commands.commands() {
+            self.notify(format!("{}: {}", command.name, command.description));
+        }
+        Ok(())
+    }
+
+    fn explain_command(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        let name = format!("/{}", name.trim_start_matches('/'));
+        let server_controller = self.server_controller.clone();
+        let command = server_controller
+            .commands
+            .get(&name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown command {}, see /help", name))?;
+        self.notify(
+            format!("{} {}", command.name, command.usage)
+                .trim_end()
+                .to_string(),
+        );
+        self.notify(command.description.to_string());
+        Ok(())
+    }
+
+    async fn set_nickname(&mut self, nickname: Option<&str>) -> Result<(), anyhow::Error> {
+        self.server_controller
+            .set_nickname(&self.app_state.user, nickname)
+            .await?;
+        match nickname {
+            Some(nickname) => self.notify(format!("You are now known as {}", nickname)),
+            None => self.notify("Cleared your nickname".to_string()),
+        }
+        Ok(())
+    }
+
+    fn show_topic(&mut self) -> Result<(), anyhow::Error> {
+        let channel = self
+            .app_state
+            .current_channel()
+            .ok_or_else(|| anyhow::anyhow!("Join a channel first"))?;
+        let notice = match &channel.topic {
+            Some(topic) => format!(
+                "Topic of {}: {}",
+                channel.display_name(&self.app_state.user),
+                topic
+            ),
+            None => format!(
+                "{} has no topic",
+                channel.display_name(&self.app_state.user)
+            ),
+        };
+        self.notify(notice);
+        Ok(())
+    }
+
+    async fn set_topic(&mut self, topic: Option<&str>) -> Result<(), anyhow::Error> {
+        let channel_id = self
+            .app_state
+            .current_channel()
+            .ok_or_else(|| anyhow::anyhow!("Join a channel first"))?
+            .id;
+        self.server_controller
+            .set_channel_topic(&self.app_state.user, channel_id, topic)
+            .await
+    }
+
+    async fn list_members(&mut self) -> Result<(), anyhow::Error> {
+        let channel = self
+            .app_state
+            .current_channel()
+            .ok_or_else(|| anyhow::anyhow!("Join a channel first"))?;
+        let channel_name = channel.display_name(&self.app_state.user);
+        let members = self
+            .server_controller
+            .get_channel_members(channel.id)
+            .await?;
+        let (online, offline): (Vec<String>, Vec<String>) =
+            members.into_iter().partition(|member| {
+                self.app_state
+                    .users
+                    .iter()
+                    .any(|user| &user.username == member)
+            });
+        let names = |members: Vec<String>| -> String {
+            members
+                .iter()
+                .map(|member| {
+                    let name = self.app_state.mention_name(member);
+                    match self.app_state.statuses.get(member) {
+                        Some(status) if status.presence != Presence::Online => {
+                            format!("{} ({})", name, presence_name(status.presence))
+                        }
+                        _ => name,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut notice = format!(
+            "{} members of {}",
+            online.len() + offline.len(),
+            channel_name
+        );
+        if !online.is_empty() {
+            notice.push_str(&format!(", online: {}", names(online)));
+        }
+        if !offline.is_empty() {
+            notice.push_str(&format!(", offline: {}", names(offline)));
+        }
+        self.notify(notice);
+        Ok(())
+    }
+
+    /// The message `command` was prepared for in navigate mode, or the
+    /// user's last one in the current channel.
+    fn target_message_id(&mut self, command: &str) -> Option<i64> {
+        self.take_command_target(command)
+            .or_else(|| self.last_own_message_id())
+    }
+
+    async fn edit_target_message(&mut self, content: String) -> Result<(), anyhow::Error> {
+        let message_id = self
+            .target_message_id("/edit")
+            .ok_or_else(|| anyhow::anyhow!("You have no message to edit here"))?;
+        self.server_controller
+            .edit_message(&self.app_state.user, message_id, content)
+            .await
+    }
+
+    async fn delete_target_message(&mut self) -> Result<(), anyhow::Error> {
+        let message_id = self
+            .target_message_id("/delete")
+            .ok_or_else(|| anyhow::anyhow!("You have no message to delete here"))?;
+        self.server_controller
+            .delete_message(&self.app_state.user, message_id)
+            .await
+    }
+
+    async fn add_session_key(&mut self) -> Result<(), anyhow::Error> {
+        let key = self.session_key.clone().ok_or_else(|| {
+            anyhow::anyhow!("Your client did not offer a key, paste one with /key add <key>")
+        })?;
+        self.server_controller
+            .add_user_key(&self.app_state.user, &key)
+            .await?;
+        self.notify("Registered the key you are connected with".to_string());
+        Ok(())
+    }
+
+    async fn add_key(&mut self, key: &str) -> Result<(), anyhow::Error> {
+        let key = PublicKey::from_openssh(key)
+            .map_err(|_e| anyhow::anyhow!("Not a valid OpenSSH public key"))?;
+        self.server_controller
+            .add_user_key(&self.app_state.user, &key)
+            .await?;
+        self.notify("Registered key".to_string());
+        Ok(())
+    }
+
+    async fn list_keys(&mut self) -> Result<(), anyhow::Error> {
+        let keys = self
+            .server_controller
+            .get_user_keys(&self.app_state.user)
+            .await?;
+        if keys.is_empty() {
+            self.notify("No keys registered".to_string());
+        }
+        for key in keys {
+            self.notify(format!("{} {} {}", key.id, key.fingerprint(), key.comment));
+        }
+        Ok(())
+    }
+
+    async fn revoke_key(&mut self, id: &str) -> Result<(), anyhow::Error> {
+        let id = id
+            .parse::<i64>()
+            .map_err(|_e| anyhow::anyhow!("Key id must be a number"))?;
+        self.server_controller
+            .revoke_user_key(&self.app_state.user, id)
+            .await?;
+        self.notify(format!("Revoked key {}", id));
+        Ok(())
+    }
+
+    /// Without `validity` the invite is single-use, otherwise it can be used
+    /// until it expires, e.g. after `24h` or `7d`.
+    async fn create_invite(&mut self, validity: Option<&str>) -> Result<(), anyhow::Error> {
+        self.server_controller.require_admin(&self.app_state.user)?;
+        let expires_in = validity.map(parse_validity).transpose()?;
+        let invite = self
+            .server_controller
+            .create_invite(&self.app_state.user, expires_in)
+            .await?;
+        let kind = match invite.expires_at {
+            Some(expires_at) => format!(
+                "valid until {}",
+                expires_at.with_timezone(&Local).format("%b %d %H:%M")
+            ),
+            None => "single use".to_string(),
+        };
+        self.notify(format!("Invite code {} ({})", invite.code, kind));
+        Ok(())
+    }
+
+    async fn list_invites(&mut self) -> Result<(), anyhow::Error> {
+        let invites = self
+            .server_controller
+            .get_invites(&self.app_state.user)
+            .await?;
+        let now = Utc::now();
+        let invites: Vec<_> = invites
+            .into_iter()
+            .filter(|invite| invite.is_valid(now))
+            .collect();
+        if invites.is_empty() {
+            self.notify("No open invites".to_string());
+        }
+        for invite in invites {
+            let kind = match invite.expires_at {
+                Some(expires_at) => format!(
+                    "until {}, used {} times",
+                    expires_at.with_timezone(&Local).format("%b %d %H:%M"),
+                    invite.uses
+                ),
+                None => "single use".to_string(),
+            };
+            self.notify(format!(
+                "{} by {} ({})",
+                invite.code, invite.created_by, kind
+            ));
+        }
+        Ok(())
+    }
+
+    async fn revoke_invite(&mut self, code: &str) -> Result<(), anyhow::Error> {
+        self.server_controller
+            .revoke_invite(&self.app_state.user, code)
+            .await?;
+        self.notify(format!("Revoked invite {}", code));
+        Ok(())
+    }
+
+    async fn add_account(&mut self, username: &str, password: &str) -> Result<(), anyhow::Error> {
+        let username = username.trim_start_matches('@');
+        self.server_controller
+            .add_account(&self.app_state.user, username, password)
+            .await?;
+        self.notify(format!("Created the account @{}", username));
+        Ok(())
+    }
+
+    async fn search_command(&mut self, arguments: &[&str]) -> Result<(), anyhow::Error> {
+        let mut search = MessageSearch::default();
+        for argument in arguments {
+            match argument.split_once(':') {
+                Some(("from", sender)) => {
+                    search.sender = Some(sender.trim_start_matches('@').to_string());
+                }
+                Some(("in", name)) => {
+                    let index = Channel::parse_name(name)
+                        .and_then(|name| self.channel_index(&name))
+                        .ok_or_else(|| anyhow::anyhow!("You are not in {}", name))?;
+                    search.channel_id = Some(self.app_state.channels[index].id);
+                }
+                Some(("after", date)) => search.after = Some(parse_date(date)?),
+                Some(("before", date)) => search.before = Some(parse_date(date)?),
+                _ => search.words.extend(MessageSearch::split_words(argument)),
+            }
+        }
+        self.search(&arguments.join(" "), search).await
+    }
+
+    async fn join_channel(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        let name = Channel::parse_name(name)
+            .ok_or_else(|| anyhow::anyhow!("Invalid channel name {}", name))?;
+
+        if let Some(index) = self.channel_index(&name) {
+            return self.switch_channel(index).await;
+        }
+
+        self.server_controller
+            .join_channel(&self.app_state.user, &name)
+            .await?;
+        self.refresh_channels().await?;
+        if let Some(index) = self.channel_index(&name) {
+            self.switch_channel(index).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_channel(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        let name = Channel::parse_name(name)
+            .ok_or_else(|| anyhow::anyhow!("Invalid channel name {}", name))?;
+        self.server_controller
+            .create_channel(&name, &self.app_state.user)
+            .await?;
+        self.join_channel(&name).await
+    }
+
+    async fn leave_channel(&mut self, name: Option<&str>) -> Result<(), anyhow::Error> {
+        let index = match name {
+            Some(name) => Channel::parse_name(name)
+                .and_then(|name| self.channel_index(&name))
+                .ok_or_else(|| anyhow::anyhow!("You are not in {}", name))?,
+            None => self.app_state.current_channel,
+        };
+        if self.app_state.channels.len() <= 1 {
+            return Err(anyhow::anyhow!("You can't leave your last channel"));
+        }
+        let channel = &self.app_state.channels[index];
+        if channel.direct {
+            return Err(anyhow::anyhow!("Direct conversations can't be left"));
+        }
+
+        let channel_id = channel.id;
+        self.server_controller
+            .leave_channel(&self.app_state.user, channel_id)
+            .await?;
+        self.refresh_channels().await?;
+        self.switch_channel(0).await
+    }
+
+    fn channel_index(&self, name: &str) -> Option<usize> {
+        self.app_state
+            .channels
+            .iter()
+            .position(|channel| channel.name == name)
+    }
+}
+
+/// Parses how long an invite stays valid, in hours (`12h`) or days (`7d`).
+fn parse_validity(input: &str) -> Result<Duration, anyhow::Error> {
+    let invalid = || anyhow::anyhow!("Invalid validity {}, use e.g. 24h or 7d", input);
+    let amount = |amount: &str| amount.parse::<i64>().ok().filter(|amount| *amount > 0);
+    let validity = if let Some(hours) = input.strip_suffix('h') {
+        amount(hours).and_then(Duration::try_hours)
+    } else if let Some(days) = input.strip_suffix('d') {
+        amount(days).and_then(Duration::try_days)
+    } else {
+        None
+    };
+    validity
+        .filter(|validity| Utc::now().checked_add_signed(*validity).is_some())
+        .ok_or_else(invalid)
+}
+
+/// Parses a search date, either a local day (`2024-05-01`) or a number of days ago (`7d`).
+fn parse_date(input: &str) -> Result<DateTime<Utc>, anyhow::Error> {
+    let invalid = || anyhow::anyhow!("Invalid date {}, use e.g. 2024-05-01 or 7d", input);
+    if let Some(days) = input.strip_suffix('d') {
+        return days
+            .parse::<i64>()
+            .ok()
+            .filter(|days| *days >= 0)
+            .and_then(Duration::try_days)
+            .and_then(|days| Utc::now().checked_sub_signed(days))
+            .ok_or_else(invalid);
+    }
+    let date = NaiveDate::parse_from_str(input, "%Y-%m-%d").map_err(|_e| invalid())?;
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+        .map(|midnight| midnight.with_timezone(&Utc))
+        .ok_or_else(invalid)
+}
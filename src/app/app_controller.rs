@@ -1,5 +1,10 @@
+use std::io::Write;
 use std::sync::Arc;
 
+use base64::prelude::*;
+
+use log::warn;
+
 use ratatui::{
     backend::CrosstermBackend,
     layout::Rect,
@@ -8,24 +13,34 @@ use ratatui::{
 };
 
 use russh::ChannelId;
+use russh::keys::PublicKey;
 
 use russh::server::{Handle, Session};
 
 use emojic::text::parse_text;
 
 use crate::{
-    app::{AppState, SshTerminal, TerminalHandle, app_state::InputMode},
-    app_server::AppServerController,
-    db_models::{Message, User},
+    app::{
+        AppState, SshTerminal, TerminalHandle,
+        app_state::{Focus, InputMode, SearchResults, Thread},
+        completion::{COMPLETION_LIMIT, Candidate, Completion, emoji_candidates, word_start},
+        composer::Composer,
+        input_history::InputHistory,
+    },
+    app_server::{AppServerController, DEFAULT_CHANNEL, SEARCH_CONTEXT},
+    db_models::{Message, MessageSearch, User},
+    server_events::ServerEvent,
 };
 
 pub struct AppController {
-    server_controller: Arc<AppServerController>,
+    pub(crate) server_controller: Arc<AppServerController>,
     pub terminal: SshTerminal,
     pub app_state: AppState,
     handle: Handle,
     channel_id: ChannelId,
     pub active: bool,
+    /// Key the client offered during authentication, which can be registered with `/key add`.
+    pub session_key: Option<PublicKey>,
 }
 
 impl AppController {
@@ -44,52 +59,1072 @@ impl AppController {
         };
 
         let terminal = Terminal::with_options(backend, options)?;
-        Ok(Self {
+        let mut channels = server_controller.get_user_channels(&user).await?;
+        if channels.is_empty() {
+            server_controller
+                .join_channel(&user, DEFAULT_CHANNEL)
+                .await?;
+            channels = server_controller.get_user_channels(&user).await?;
+        }
+
+        let input_history = server_controller.get_input_history(&user).await?;
+        let nicknames = server_controller.get_nicknames().await?;
+
+        let unseen_mentions = server_controller
+            .get_mentions(&user)
+            .await?
+            .iter()
+            .filter(|mention| !mention.seen)
+            .count();
+
+        let mut app_state = AppState::new(user);
+        app_state.mention_bell = server_controller.config.mention_bell;
+        app_state.input_history = InputHistory::new(input_history);
+        app_state.nicknames = nicknames;
+        let current_channel = channels
+            .iter()
+            .position(|channel| channel.name == DEFAULT_CHANNEL)
+            .unwrap_or(0);
+        app_state.channels = channels;
+
+        let mut controller = Self {
             server_controller,
             terminal,
-            app_state: AppState::new(user),
+            app_state,
             handle: session.handle(),
-            channel_id: channel_id,
+            channel_id,
             active: true,
-        })
+            session_key: None,
+        };
+        controller.switch_channel(current_channel).await?;
+        if unseen_mentions > 0 {
+            controller.notify(format!(
+                "You were mentioned {} time{} while away, see /mentions",
+                unseen_mentions,
+                if unseen_mentions == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(controller)
     }
 
     pub fn resize_terminal(&mut self, rect: Rect) {
         let _ = self.terminal.resize(rect);
     }
 
-    pub async fn get_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
-        let messages: Vec<Message> = self.server_controller.get_messages().await?;
-        Ok(messages)
+    /// Replaces the loaded messages with the latest page of the current channel.
+    async fn load_latest_messages(&mut self) -> Result<(), anyhow::Error> {
+        let messages = match self.app_state.current_channel() {
+            Some(channel) => {
+                self.server_controller
+                    .get_messages(channel.id, &self.app_state.user, None)
+                    .await?
+            }
+            None => Vec::new(),
+        };
+        self.app_state.older_messages =
+            messages.len() >= self.server_controller.config.history_page();
+        self.app_state.newer_messages = false;
+        self.app_state.messages = messages;
+        Ok(())
+    }
+
+    /// Loads the page of messages before the oldest loaded one. The newest
+    /// ones are dropped to keep within the history limit.
+    async fn load_older_messages(&mut self) -> Result<(), anyhow::Error> {
+        let (Some(channel), Some(oldest_id)) = (
+            self.app_state.current_channel(),
+            self.app_state
+                .messages
+                .iter()
+                .map(|message| message.id)
+                .filter(|id| *id != 0)
+                .min(),
+        ) else {
+            return Ok(());
+        };
+        let older = self
+            .server_controller
+            .get_messages(channel.id, &self.app_state.user, Some(oldest_id))
+            .await?;
+        let config = &self.server_controller.config;
+        self.app_state.older_messages = older.len() >= config.history_page();
+        self.app_state.messages.splice(..0, older);
+        if self.app_state.messages.len() > config.history_limit() {
+            self.app_state.messages.truncate(config.history_limit());
+            self.app_state.newer_messages = true;
+        }
+        self.load_reactions().await
     }
 
+    /// Loads the page of messages after the newest loaded one. The oldest
+    /// ones are dropped to keep within the history limit.
+    async fn load_newer_messages(&mut self) -> Result<(), anyhow::Error> {
+        let (Some(channel), Some(newest_id)) = (
+            self.app_state.current_channel(),
+            self.app_state
+                .messages
+                .iter()
+                .map(|message| message.id)
+                .max(),
+        ) else {
+            return Ok(());
+        };
+        let newer = self
+            .server_controller
+            .get_messages_after(channel.id, &self.app_state.user, newest_id)
+            .await?;
+        let config = &self.server_controller.config;
+        self.app_state.newer_messages = newer.len() >= config.history_page();
+        self.app_state.messages.extend(newer);
+        let overflow = self
+            .app_state
+            .messages
+            .len()
+            .saturating_sub(config.history_limit());
+        if overflow > 0 {
+            self.app_state.messages.drain(..overflow);
+            self.app_state.older_messages = true;
+        }
+        self.load_reactions().await
+    }
+
+    /// Sends a message to the current channel, or a reply while the thread pane has focus.
     pub async fn send_message(&self, message: String) -> Result<(), anyhow::Error> {
+        let channel = self
+            .app_state
+            .current_channel()
+            .ok_or_else(|| anyhow::anyhow!("Join a channel first"))?;
+        let mut message = Message::new(message, self.app_state.user.clone(), channel.id);
+        if let (true, Some(thread)) = (self.app_state.thread_focused(), &self.app_state.thread) {
+            message.parent_id = Some(thread.parent.id);
+        }
+        self.server_controller.send_message(message).await
+    }
+
+    /// Returns the id of the most recent message the user sent to the current channel.
+    pub fn last_own_message_id(&self) -> Option<i64> {
+        self.app_state
+            .messages
+            .iter()
+            .rev()
+            .find(|message| {
+                message.id != 0
+                    && !message.deleted
+                    && message.sender.username == self.app_state.user.username
+            })
+            .map(|message| message.id)
+    }
+
+    /// Shows a local notice in the message pane. It is not stored or sent to anyone else.
+    pub fn notify(&mut self, notice: String) {
+        let channel_id = self
+            .app_state
+            .current_channel()
+            .map_or(0, |channel| channel.id);
+        self.app_state
+            .messages
+            .push(Message::new(notice, User::bot(), channel_id));
+    }
+
+    pub async fn switch_channel(&mut self, index: usize) -> Result<(), anyhow::Error> {
+        if index >= self.app_state.channels.len() {
+            return Ok(());
+        }
+        self.app_state.current_channel = index;
+        self.app_state.scroll_offset = 0;
+        self.app_state.highlighted_message = None;
+        self.app_state.selected_message = None;
+        self.app_state.command_target = None;
+        self.close_thread();
+        self.load_latest_messages().await?;
+        self.load_reactions().await?;
+        self.mark_current_channel_read().await
+    }
+
+    /// Replaces the reactions with those to the loaded messages and the open thread.
+    async fn load_reactions(&mut self) -> Result<(), anyhow::Error> {
+        let thread_messages = self
+            .app_state
+            .thread
+            .iter()
+            .flat_map(|thread| std::iter::once(&thread.parent).chain(&thread.replies));
+        let message_ids: Vec<i64> = self
+            .app_state
+            .messages
+            .iter()
+            .chain(thread_messages)
+            .map(|message| message.id)
+            .filter(|id| *id != 0)
+            .collect();
+        self.app_state.reactions.clear();
+        for reaction in self.server_controller.get_reactions(&message_ids).await? {
+            self.app_state
+                .reactions
+                .entry(reaction.message_id)
+                .or_default()
+                .push(reaction);
+        }
+        Ok(())
+    }
+
+    /// Switches to navigate mode, selecting the newest message if none is selected.
+    pub async fn start_navigating(&mut self) -> Result<(), anyhow::Error> {
+        self.set_mode(InputMode::Navigate);
+        if self.app_state.focus == Focus::Messages && self.app_state.selected_message.is_none() {
+            self.select_last_message().await?;
+        }
+        Ok(())
+    }
+
+    /// Moves the selection `count` messages back. Reaching the oldest loaded
+    /// message loads the page before it.
+    pub async fn select_previous_message(&mut self, count: usize) -> Result<(), anyhow::Error> {
+        let Some(selected) = self.app_state.selected_message else {
+            self.app_state.selected_message = self.selectable_message_ids().last().copied();
+            return Ok(());
+        };
+        let mut ids = self.selectable_message_ids();
+        if ids.partition_point(|id| *id < selected) <= count && self.app_state.older_messages {
+            self.load_older_messages().await?;
+            ids = self.selectable_message_ids();
+        }
+        let index = ids
+            .partition_point(|id| *id < selected)
+            .saturating_sub(count);
+        self.app_state.selected_message = ids.get(index).copied();
+        Ok(())
+    }
+
+    /// Moves the selection `count` messages forward. Reaching the newest
+    /// loaded message loads the page after it, if there is one.
+    pub async fn select_next_message(&mut self, count: usize) -> Result<(), anyhow::Error> {
+        let Some(selected) = self.app_state.selected_message else {
+            self.app_state.selected_message = self.selectable_message_ids().last().copied();
+            return Ok(());
+        };
+        let mut ids = self.selectable_message_ids();
+        if ids.partition_point(|id| *id < selected) + count + 1 >= ids.len()
+            && self.app_state.newer_messages
+        {
+            self.load_newer_messages().await?;
+            ids = self.selectable_message_ids();
+        }
+        let index = ids.partition_point(|id| *id < selected) + count;
+        self.app_state.selected_message = ids.get(index.min(ids.len().saturating_sub(1))).copied();
+        Ok(())
+    }
+
+    /// Selects the oldest loaded message and loads the page before it.
+    pub async fn select_first_message(&mut self) -> Result<(), anyhow::Error> {
+        self.app_state.selected_message = self.selectable_message_ids().first().copied();
+        if self.app_state.older_messages {
+            self.load_older_messages().await?;
+        }
+        Ok(())
+    }
+
+    /// Selects the newest message and scrolls to the bottom, below any notices
+    /// after it. The latest page is loaded first if newer messages exist.
+    pub async fn select_last_message(&mut self) -> Result<(), anyhow::Error> {
+        if self.app_state.newer_messages {
+            self.load_latest_messages().await?;
+            self.load_reactions().await?;
+        }
+        self.app_state.selected_message = self.selectable_message_ids().last().copied();
+        self.app_state.scroll_offset = 0;
+        Ok(())
+    }
+
+    /// Messages that Ctrl-D and Ctrl-U move the selection by.
+    pub fn half_page(&self) -> usize {
+        (self.app_state.message_rows / 2).max(1)
+    }
+
+    /// The selected message, for the navigate mode action keys.
+    fn selected_message(&self) -> Result<&Message, anyhow::Error> {
+        self.app_state
+            .selected_message
+            .and_then(|id| {
+                self.app_state
+                    .messages
+                    .iter()
+                    .find(|message| message.id == id)
+            })
+            .ok_or_else(|| anyhow::anyhow!("Select a message first"))
+    }
+
+    /// Puts a command for a message in the input box, to be completed or
+    /// confirmed with Enter. The message is only used if the command sent is
+    /// still the one prepared.
+    fn prepare_command(&mut self, command: &str, message_id: i64) {
+        let name = command.split_whitespace().next().unwrap_or(command);
+        self.app_state.command_target = Some((name.to_string(), message_id));
+        self.app_state.composer.set_text(command.to_string());
+        self.set_mode(InputMode::Insert);
+    }
+
+    /// The message `command` applies to when it was prepared from navigate
+    /// mode. That message is no longer selected afterwards.
+    pub fn take_command_target(&mut self, command: &str) -> Option<i64> {
+        let (name, message_id) = self.app_state.command_target.take()?;
+        if name != command {
+            return None;
+        }
+        self.app_state.selected_message = None;
+        Some(message_id)
+    }
+
+    /// Starts a reaction to the selected message, selecting the last one
+    /// when none is.
+    pub async fn react_to_selected_message(&mut self) -> Result<(), anyhow::Error> {
+        if self.app_state.selected_message.is_none() {
+            self.select_last_message().await?;
+        }
+        let message_id = self.selected_message()?.id;
+        self.prepare_command("/react :", message_id);
+        Ok(())
+    }
+
+    /// Starts editing the selected message, which must be the user's own.
+    pub fn edit_selected_message(&mut self) -> Result<(), anyhow::Error> {
+        let message = self.selected_message()?;
+        if message.sender.username != self.app_state.user.username {
+            return Err(anyhow::anyhow!("You can only edit your own messages"));
+        }
+        let command = format!("/edit {}", message.content);
+        self.prepare_command(&command, message.id);
+        Ok(())
+    }
+
+    /// Asks to confirm deleting the selected message, which must be the user's own.
+    pub fn delete_selected_message(&mut self) -> Result<(), anyhow::Error> {
+        let message = self.selected_message()?;
+        if message.sender.username != self.app_state.user.username {
+            return Err(anyhow::anyhow!("You can only delete your own messages"));
+        }
+        self.prepare_command("/delete", message.id);
+        Ok(())
+    }
+
+    /// Copies the selected message to the clipboard of the user's terminal
+    /// with an OSC 52 escape sequence, which not every terminal supports.
+    pub fn copy_selected_message(&mut self) -> Result<(), anyhow::Error> {
+        let content = self.selected_message()?.content.clone();
+        let sequence = format!("\x1b]52;c;{}\x07", BASE64_STANDARD.encode(content));
+        let backend = self.terminal.backend_mut();
+        backend.write_all(sequence.as_bytes())?;
+        backend.flush()?;
+        self.notify("Copied the message to your clipboard".to_string());
+        Ok(())
+    }
+
+    fn selectable_message_ids(&self) -> Vec<i64> {
+        self.app_state
+            .messages
+            .iter()
+            .filter(|message| message.id != 0 && !message.deleted)
+            .map(|message| message.id)
+            .collect()
+    }
+
+    /// Opens the thread of a message, or of the message a reply belongs to,
+    /// with the thread pane focused for writing a reply.
+    pub async fn open_thread(&mut self, message_id: i64) -> Result<(), anyhow::Error> {
+        let mut parent = self.server_controller.get_message(message_id).await?;
+        if let Some(parent_id) = parent.parent_id {
+            parent = self.server_controller.get_message(parent_id).await?;
+        }
+        let replies = self
+            .server_controller
+            .get_replies(&self.app_state.user, parent.id)
+            .await?;
+        self.app_state.thread = Some(Thread {
+            parent,
+            replies,
+            composer: Composer::default(),
+            scroll_offset: 0,
+        });
+        self.app_state.focus = Focus::Thread;
+        self.set_mode(InputMode::Insert);
+        self.load_reactions().await
+    }
+
+    /// Opens the thread of the selected message, or of the newest one if none is selected.
+    pub async fn open_selected_thread(&mut self) -> Result<(), anyhow::Error> {
+        let message_id = self
+            .app_state
+            .selected_message
+            .take()
+            .or_else(|| self.selectable_message_ids().last().copied())
+            .ok_or_else(|| anyhow::anyhow!("There is no message to reply to"))?;
+        self.open_thread(message_id).await
+    }
+
+    pub fn close_thread(&mut self) {
+        self.app_state.thread = None;
+        if self.app_state.focus == Focus::Thread {
+            self.app_state.focus = Focus::Messages;
+        }
+    }
+
+    /// Toggles the user's reaction to the message `/react` was prepared for,
+    /// or to the newest one.
+    pub async fn react(&mut self, emoji: &str) -> Result<(), anyhow::Error> {
+        let message_id = self
+            .take_command_target("/react")
+            .or_else(|| self.selectable_message_ids().last().copied())
+            .ok_or_else(|| anyhow::anyhow!("There is no message to react to"))?;
         self.server_controller
-            .send_message(Message::new(message, self.app_state.user.clone()))
+            .toggle_reaction(&self.app_state.user, message_id, emoji)
+            .await?;
+        Ok(())
+    }
+
+    /// Puts the "new messages" divider above the first loaded message the user
+    /// hadn't read in the current channel, then marks the channel read.
+    async fn mark_current_channel_read(&mut self) -> Result<(), anyhow::Error> {
+        let Some(channel_id) = self.app_state.current_channel().map(|channel| channel.id) else {
+            return Ok(());
+        };
+        let user = &self.app_state.user;
+        let markers = self.server_controller.get_read_markers(user).await?;
+        let last_read_id = markers
+            .iter()
+            .find(|marker| marker.channel_id == channel_id)
+            .map_or(0, |marker| marker.last_read_id);
+        self.app_state.first_unread = self
+            .app_state
+            .messages
+            .iter()
+            .find(|message| message.id > last_read_id && message.sender.username != user.username)
+            .map(|message| message.id);
+        self.app_state.unread = markers
+            .into_iter()
+            .filter(|marker| marker.channel_id != channel_id)
+            .map(|marker| (marker.channel_id, marker.unread))
+            .collect();
+        if let Some(last_id) = self.app_state.messages.iter().map(|m| m.id).max() {
+            self.server_controller
+                .mark_read(&self.app_state.user, channel_id, last_id)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Scrolls the message pane to the "new messages" divider.
+    pub fn jump_to_first_unread(&mut self) {
+        if self.app_state.first_unread.is_some() {
+            self.app_state.jump_to_unread = true;
+            self.app_state.selected_message = self.app_state.first_unread;
+        } else {
+            self.notify("No new messages".to_string());
+        }
+    }
+
+    /// Runs a search and shows its results in the search pane.
+    pub async fn search(
+        &mut self,
+        query: &str,
+        search: MessageSearch,
+    ) -> Result<(), anyhow::Error> {
+        let messages = self
+            .server_controller
+            .search_messages(&self.app_state.user, &search)
+            .await?;
+        let title = format!("Search: {} ({} results)", query, messages.len());
+        self.show_results(title, messages, Vec::new());
+        Ok(())
+    }
+
+    /// Lists recent mentions of the user in the search pane, with the ones
+    /// they hadn't seen in bold, and marks them all seen.
+    pub async fn show_mentions(&mut self) -> Result<(), anyhow::Error> {
+        let mentions = self
+            .server_controller
+            .get_mentions(&self.app_state.user)
+            .await?;
+        let unseen: Vec<i64> = mentions
+            .iter()
+            .filter(|mention| !mention.seen)
+            .map(|mention| mention.message.id)
+            .collect();
+        if !unseen.is_empty() {
+            self.server_controller
+                .mark_mentions_seen(&self.app_state.user)
+                .await?;
+        }
+        let title = format!("Mentions ({} new)", unseen.len());
+        let messages = mentions
+            .into_iter()
+            .map(|mention| mention.message)
+            .collect();
+        self.show_results(title, messages, unseen);
+        Ok(())
+    }
+
+    fn show_results(&mut self, title: String, messages: Vec<Message>, unseen: Vec<i64>) {
+        self.app_state.search = Some(SearchResults {
+            title,
+            messages,
+            unseen,
+            selected: 0,
+        });
+        self.app_state.focus = Focus::Search;
+        self.set_mode(InputMode::Navigate);
+    }
+
+    /// Rings the bell for a message mentioning the user, and points to it
+    /// when it was sent to another channel.
+    fn alert_mention(&mut self, message: &Message, in_current_channel: bool) {
+        if self.app_state.mention_bell {
+            let backend = self.terminal.backend_mut();
+            if let Err(e) = backend.write_all(b"\x07").and_then(|_| backend.flush()) {
+                warn!("Failed to ring the bell: {}", e);
+            }
+        }
+        if in_current_channel {
+            return;
+        }
+        let channel = self
+            .app_state
+            .channels
+            .iter()
+            .find(|channel| channel.id == message.channel_id)
+            .map(|channel| {
+                if channel.direct {
+                    "a direct message".to_string()
+                } else {
+                    channel.display_name(&self.app_state.user)
+                }
+            })
+            .unwrap_or_default();
+        let sender = self.app_state.mention_name(&message.sender.username);
+        self.notify(format!(
+            "{} mentioned you in {}: {}",
+            sender,
+            channel,
+            message.content.replace('\n', " ")
+        ));
+    }
+
+    pub fn close_search(&mut self) {
+        self.app_state.search = None;
+        if self.app_state.focus == Focus::Search {
+            self.app_state.focus = Focus::Messages;
+        }
+    }
+
+    pub fn select_previous_result(&mut self) {
+        if let Some(search) = &mut self.app_state.search {
+            search.selected = search.selected.saturating_sub(1);
+        }
+    }
+
+    pub fn select_next_result(&mut self) {
+        if let Some(search) = &mut self.app_state.search {
+            let last = search.messages.len().saturating_sub(1);
+            search.selected = (search.selected + 1).min(last);
+        }
+    }
+
+    /// Opens the channel of the selected search result, scrolled to the result.
+    pub async fn jump_to_selected_result(&mut self) -> Result<(), anyhow::Error> {
+        let Some(message) = self
+            .app_state
+            .search
+            .as_ref()
+            .and_then(|search| search.messages.get(search.selected))
+            .cloned()
+        else {
+            return Ok(());
+        };
+        let index = self
+            .app_state
+            .channels
+            .iter()
+            .position(|channel| channel.id == message.channel_id)
+            .ok_or_else(|| anyhow::anyhow!("You are no longer in that channel"))?;
+
+        // Replies are only listed in their thread, so show the message they reply to.
+        let target_id = message.parent_id.unwrap_or(message.id);
+        self.app_state.current_channel = index;
+        self.app_state.messages = self
+            .server_controller
+            .get_messages_around(message.channel_id, &self.app_state.user, target_id)
+            .await?;
+        let older = self
+            .app_state
+            .messages
+            .iter()
+            .filter(|m| m.id < target_id)
+            .count();
+        // Leave a few newer messages visible below the result.
+        let newer = self
+            .app_state
+            .messages
+            .iter()
+            .filter(|m| m.id > target_id)
+            .count();
+        self.app_state.older_messages = older >= SEARCH_CONTEXT;
+        self.app_state.newer_messages = newer >= self.server_controller.config.history_page();
+        self.app_state.scroll_offset = newer.saturating_sub(5) as u16;
+        self.app_state.highlighted_message = Some(target_id);
+        self.app_state.selected_message = Some(target_id);
+        self.app_state.focus = Focus::Messages;
+        self.close_thread();
+        self.load_reactions().await?;
+        self.mark_current_channel_read().await?;
+        if message.parent_id.is_some() {
+            self.open_thread(target_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the channel list, keeping the current channel selected.
+    pub async fn refresh_channels(&mut self) -> Result<(), anyhow::Error> {
+        let current_id = self.app_state.current_channel().map(|channel| channel.id);
+        self.app_state.channels = self
+            .server_controller
+            .get_user_channels(&self.app_state.user)
+            .await?;
+        self.app_state.current_channel = self
+            .app_state
+            .channels
+            .iter()
+            .position(|channel| Some(channel.id) == current_id)
+            .unwrap_or(0);
+        Ok(())
+    }
+
+    pub async fn open_direct_channel(
+        &mut self,
+        recipients: &[String],
+    ) -> Result<(), anyhow::Error> {
+        let channel = self
+            .server_controller
+            .open_direct_channel(&self.app_state.user, recipients)
+            .await?;
+        self.refresh_channels().await?;
+        if let Some(index) = self
+            .app_state
+            .channels
+            .iter()
+            .position(|c| c.id == channel.id)
+        {
+            self.switch_channel(index).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn open_selected_direct_channel(&mut self) -> Result<(), anyhow::Error> {
+        let Some(user) = self.app_state.users.get(self.app_state.selected_user) else {
+            return Ok(());
+        };
+        let recipients = vec![user.username.clone()];
+        self.open_direct_channel(&recipients).await?;
+        self.app_state.focus = Focus::Messages;
+        self.set_mode(InputMode::Insert);
+        Ok(())
+    }
+
+    pub fn toggle_focus(&mut self) {
+        self.app_state.focus = match self.app_state.focus {
+            Focus::Messages => Focus::Users,
+            Focus::Users if self.app_state.search.is_some() => Focus::Search,
+            Focus::Users | Focus::Search if self.app_state.thread.is_some() => Focus::Thread,
+            Focus::Users | Focus::Search | Focus::Thread => Focus::Messages,
+        };
+    }
+
+    pub fn select_previous_user(&mut self) {
+        self.app_state.selected_user = self.app_state.selected_user.saturating_sub(1);
+    }
+
+    pub fn select_next_user(&mut self) {
+        let last = self.app_state.users.len().saturating_sub(1);
+        self.app_state.selected_user = (self.app_state.selected_user + 1).min(last);
+    }
+
+    pub async fn next_channel(&mut self) -> Result<(), anyhow::Error> {
+        let count = self.app_state.channels.len().max(1);
+        self.switch_channel((self.app_state.current_channel + 1) % count)
+            .await
+    }
+
+    pub async fn previous_channel(&mut self) -> Result<(), anyhow::Error> {
+        let count = self.app_state.channels.len().max(1);
+        self.switch_channel((self.app_state.current_channel + count - 1) % count)
             .await
     }
 
     pub fn write_to_input(&mut self, char: Option<char>) {
+        let composer = self.app_state.active_composer_mut();
         if let Some(char) = char {
-            self.app_state.input_message.push(char);
+            composer.insert(char);
         } else {
-            self.app_state.input_message.pop();
+            composer.backspace();
         }
-        self.app_state.input_message = parse_text(&self.app_state.input_message);
+        composer.replace_before_cursor(parse_text);
     }
 
     pub fn clear_input(&mut self) {
-        self.app_state.input_message.clear();
+        self.app_state.active_composer_mut().clear();
+        self.app_state.command_target = None;
     }
 
     pub fn get_input_message(&self) -> String {
-        self.app_state.input_message.clone()
+        self.app_state.active_composer().text().to_string()
+    }
+
+    /// Completes the word before the cursor, or moves to the next candidate
+    /// (the previous one if `backwards`) while the completion popup is open.
+    pub async fn complete(&mut self, backwards: bool) {
+        if let Some(completion) = &mut self.app_state.completion {
+            let count = completion.candidates.len();
+            completion.selected = if backwards {
+                (completion.selected + count - 1) % count
+            } else {
+                (completion.selected + 1) % count
+            };
+            let start = completion.start;
+            let replacement = completion.candidates[completion.selected]
+                .replacement
+                .clone();
+            self.app_state
+                .active_composer_mut()
+                .replace_from(start, &replacement);
+            return;
+        }
+
+        let composer = self.app_state.active_composer();
+        let start = word_start(composer.text(), composer.cursor());
+        let word = composer.text()[start..composer.cursor()].to_string();
+        let candidates = if let Some(prefix) = word.strip_prefix('@') {
+            self.username_candidates(prefix).await
+        } else if let Some(prefix) = word.strip_prefix(':').filter(|p| !p.is_empty()) {
+            emoji_candidates(prefix)
+        } else if start == 0 && word.starts_with('/') {
+            self.server_controller
+                .commands
+                .commands()
+                .iter()
+                .filter(|command| command.name.starts_with(&word))
+                .map(|command| Candidate::new(format!("{} ", command.name)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let Some(first) = candidates.first() else {
+            return;
+        };
+        self.app_state
+            .active_composer_mut()
+            .replace_from(start, &first.replacement);
+        if candidates.len() > 1 {
+            self.app_state.completion = Some(Completion {
+                start,
+                candidates,
+                selected: 0,
+            });
+        }
+    }
+
+    /// Closes the completion popup, keeping the chosen candidate.
+    pub fn end_completion(&mut self) {
+        self.app_state.completion = None;
+    }
+
+    /// Online users first, then everyone else with an account.
+    async fn username_candidates(&self, prefix: &str) -> Vec<Candidate> {
+        let mut usernames: Vec<String> = self
+            .app_state
+            .users
+            .iter()
+            .filter(|user| user.username.starts_with(prefix))
+            .map(|user| user.username.clone())
+            .collect();
+        usernames.sort();
+        let known = self
+            .server_controller
+            .find_usernames(prefix, COMPLETION_LIMIT)
+            .await
+            .unwrap_or_default();
+        for username in known {
+            if !usernames.contains(&username) {
+                usernames.push(username);
+            }
+        }
+        usernames.truncate(COMPLETION_LIMIT);
+        usernames
+            .into_iter()
+            .map(|username| Candidate::new(format!("@{}", username)))
+            .collect()
+    }
+
+    /// Tells others in the channel whether the user is typing, after input
+    /// that may have changed the composer from `before`. Commands don't count.
+    pub async fn report_typing(&mut self, before: &str) {
+        let text = self.app_state.active_composer().text();
+        let Some(channel) = self.app_state.current_channel() else {
+            return;
+        };
+        if text == before {
+            return;
+        }
+        let typing = !text.trim().is_empty() && !text.starts_with('/');
+        self.server_controller
+            .set_typing(&self.app_state.user, channel.id, typing)
+            .await;
+    }
+
+    /// Saves a sent input to the history, unless it repeats the last one.
+    pub async fn record_input(&mut self, input: &str) {
+        // `/user add` carries a password in plain text.
+        if input.starts_with("/user ") || !self.app_state.input_history.push(input) {
+            return;
+        }
+        if let Err(e) = self
+            .server_controller
+            .add_input_history(&self.app_state.user, input)
+            .await
+        {
+            warn!("Failed to save input history: {}", e);
+        }
+    }
+
+    /// Moves up a line in the input, or recalls the previous input on the first line.
+    pub fn history_previous(&mut self) {
+        if self.app_state.active_composer_mut().move_up() {
+            return;
+        }
+        let current = self.app_state.active_composer().text().to_string();
+        if let Some(entry) = self
+            .app_state
+            .input_history
+            .older(&current)
+            .map(str::to_string)
+        {
+            self.app_state.active_composer_mut().set_text(entry);
+        }
     }
 
-    pub async fn draw(&mut self) -> Result<(), anyhow::Error> {
-        self.app_state.messages = self.get_messages().await?;
+    /// Moves down a line in the input, or recalls the next input on the last line.
+    pub fn history_next(&mut self) {
+        if self.app_state.active_composer_mut().move_down() {
+            return;
+        }
+        if let Some(entry) = self.app_state.input_history.newer() {
+            self.app_state.active_composer_mut().set_text(entry);
+        }
+    }
+
+    /// Starts a reverse search, or finds an older match if one is running.
+    pub fn search_history(&mut self) {
+        if self.app_state.input_history.search().is_none() {
+            let current = self.app_state.active_composer().text().to_string();
+            self.app_state.input_history.start_search(&current);
+        } else if let Some(entry) = self
+            .app_state
+            .input_history
+            .search_older()
+            .map(str::to_string)
+        {
+            self.app_state.active_composer_mut().set_text(entry);
+        }
+    }
+
+    /// Adds a char to the search query, or removes the last one.
+    pub fn edit_history_search(&mut self, char: Option<char>) {
+        let history = &mut self.app_state.input_history;
+        let Some(search) = history.search() else {
+            return;
+        };
+        let mut query = search.query.clone();
+        if let Some(char) = char {
+            query.push(char);
+        } else {
+            query.pop();
+        }
+        if let Some(entry) = history.set_search_query(query).map(str::to_string) {
+            self.app_state.active_composer_mut().set_text(entry);
+        }
+    }
+
+    pub fn accept_history_search(&mut self) {
+        self.app_state.input_history.accept_search();
+    }
+
+    pub fn cancel_history_search(&mut self) {
+        let draft = self.app_state.input_history.cancel_search();
+        self.app_state.active_composer_mut().set_text(draft);
+    }
+
+    pub async fn handle_event(&mut self, event: ServerEvent) {
+        match event {
+            ServerEvent::NewMessage(message) => {
+                let in_current_channel = self
+                    .app_state
+                    .current_channel()
+                    .is_some_and(|channel| channel.id == message.channel_id);
+                if self.app_state.alerts_viewer(&message) {
+                    self.alert_mention(&message, in_current_channel);
+                }
+                if !in_current_channel {
+                    let is_member = self
+                        .app_state
+                        .channels
+                        .iter()
+                        .any(|channel| channel.id == message.channel_id);
+                    if is_member
+                        && message.parent_id.is_none()
+                        && message.sender.username != self.app_state.user.username
+                    {
+                        *self.app_state.unread.entry(message.channel_id).or_default() += 1;
+                    }
+                    return;
+                }
+                if let Some(parent_id) = message.parent_id {
+                    if let Some(thread) = &mut self.app_state.thread
+                        && thread.parent.id == parent_id
+                        && !thread.replies.iter().any(|reply| reply.id == message.id)
+                    {
+                        thread.replies.push(message);
+                    }
+                    return;
+                }
+                // Skip messages already loaded from the database while switching
+                // channels, and ones after a gap that scrolling down will load.
+                let last_id = self.app_state.messages.iter().map(|m| m.id).max();
+                if last_id.is_some_and(|id| id >= message.id) || self.app_state.newer_messages {
+                    return;
+                }
+                if let Err(e) = self
+                    .server_controller
+                    .mark_read(&self.app_state.user, message.channel_id, message.id)
+                    .await
+                {
+                    warn!("Failed to mark {} read: {}", message.id, e);
+                }
+                self.app_state.messages.push(message);
+                let overflow = self
+                    .app_state
+                    .messages
+                    .len()
+                    .saturating_sub(self.server_controller.config.history_limit());
+                if overflow > 0 {
+                    self.app_state.messages.drain(..overflow);
+                    self.app_state.older_messages = true;
+                }
+            }
+            ServerEvent::MessageUpdated(message) => {
+                let thread_messages = self.app_state.thread.iter_mut().flat_map(|thread| {
+                    std::iter::once(&mut thread.parent).chain(&mut thread.replies)
+                });
+                for existing in self.app_state.messages.iter_mut().chain(thread_messages) {
+                    if existing.id == message.id {
+                        *existing = message.clone();
+                    }
+                }
+            }
+            ServerEvent::ReactionsChanged {
+                message_id,
+                reactions,
+            } => {
+                let in_thread = self.app_state.thread.as_ref().is_some_and(|thread| {
+                    thread.parent.id == message_id
+                        || thread.replies.iter().any(|reply| reply.id == message_id)
+                });
+                let loaded = in_thread
+                    || self
+                        .app_state
+                        .messages
+                        .iter()
+                        .any(|message| message.id == message_id);
+                if !loaded {
+                    return;
+                }
+                if reactions.is_empty() {
+                    self.app_state.reactions.remove(&message_id);
+                } else {
+                    self.app_state.reactions.insert(message_id, reactions);
+                }
+            }
+            ServerEvent::UserJoined(_) | ServerEvent::UserLeft(_) => {
+                self.app_state.users = self.get_users().await;
+                self.app_state.statuses = self.server_controller.get_statuses().await;
+                let last = self.app_state.users.len().saturating_sub(1);
+                self.app_state.selected_user = self.app_state.selected_user.min(last);
+            }
+            ServerEvent::Typing {
+                username,
+                channel_id,
+                typing,
+            } => {
+                if username == self.app_state.user.username {
+                    return;
+                }
+                let typers = self.app_state.typing.entry(channel_id).or_default();
+                typers.retain(|typer| *typer != username);
+                if typing {
+                    typers.push(username);
+                }
+            }
+            ServerEvent::StatusChanged { username, status } => {
+                self.app_state.statuses.insert(username, status);
+            }
+            ServerEvent::NicknameChanged { username, nickname } => match nickname {
+                Some(nickname) => {
+                    self.app_state.nicknames.insert(username, nickname);
+                }
+                None => {
+                    self.app_state.nicknames.remove(&username);
+                }
+            },
+            ServerEvent::TopicChanged {
+                channel_id,
+                topic,
+                set_by,
+            } => {
+                let Some(channel) = self
+                    .app_state
+                    .channels
+                    .iter_mut()
+                    .find(|channel| channel.id == channel_id)
+                else {
+                    return;
+                };
+                channel.topic = topic.clone();
+                let in_current_channel = self
+                    .app_state
+                    .current_channel()
+                    .is_some_and(|channel| channel.id == channel_id);
+                if in_current_channel {
+                    let setter = self.app_state.mention_name(&set_by.username);
+                    self.notify(match topic {
+                        Some(topic) => format!("{} set the topic: {}", setter, topic),
+                        None => format!("{} cleared the topic", setter),
+                    });
+                }
+            }
+            ServerEvent::DirectChannelOpened(channel) => {
+                let is_member = channel.members.contains(&self.app_state.user.username);
+                if is_member {
+                    let _ = self.refresh_channels().await;
+                }
+            }
+        }
+    }
+
+    pub async fn reload(&mut self) -> Result<(), anyhow::Error> {
+        self.load_latest_messages().await?;
+        self.load_reactions().await?;
         self.app_state.users = self.get_users().await;
+        self.app_state.statuses = self.server_controller.get_statuses().await;
+        Ok(())
+    }
 
+    pub fn draw(&mut self) -> Result<(), anyhow::Error> {
         self.terminal.draw(|frame| {
             frame.render_widget(Clear, frame.area());
             frame.render_widget(&mut self.app_state, frame.area());
@@ -112,14 +1147,24 @@ impl AppController {
     }
 
     pub fn scroll_up(&mut self, count: u16) {
+        if let (true, Some(thread)) = (self.app_state.thread_focused(), &mut self.app_state.thread)
+        {
+            thread.scroll_offset = thread.scroll_offset.saturating_sub(count);
+            return;
+        }
         if (self.app_state.scroll_offset) >= count {
-            self.app_state.scroll_offset = self.app_state.scroll_offset - count;
+            self.app_state.scroll_offset -= count;
         } else {
             self.app_state.scroll_offset = 0;
         }
     }
 
     pub fn scroll_down(&mut self, count: u16) {
-        self.app_state.scroll_offset = self.app_state.scroll_offset + count;
+        if let (true, Some(thread)) = (self.app_state.thread_focused(), &mut self.app_state.thread)
+        {
+            thread.scroll_offset += count;
+            return;
+        }
+        self.app_state.scroll_offset += count;
     }
 }
@@ -2,70 +2,332 @@ use terminal_keycode::Decoder;
 
 use terminal_keycode::KeyCode;
 
-use crate::app::{App, app_state::InputMode};
+use crate::app::{
+    App, AppController,
+    app_state::{Focus, InputMode},
+};
 
 impl App {
     pub async fn process_input_data(&mut self, data: &[u8]) -> Result<(), anyhow::Error> {
-        if let Some(controller) = &self.app_controller {
+        if let Some(controller) = self.app_controller.clone() {
             let mut controller = controller.lock().await;
-            match controller.app_state.input_mode {
-                InputMode::Insert => {
-                    for &byte in data {
-                        for keycode in self.decoder.write(byte) {
-                            match keycode {
-                                KeyCode::CtrlN => {
-                                    controller.set_mode(InputMode::Navigate);
-                                    self.decoder = Decoder::new();
-                                }
-                                KeyCode::Char(c) => {
-                                    controller.write_to_input(Some(c));
-                                }
-                                KeyCode::Space => {
-                                    controller.write_to_input(Some(' '));
-                                }
-                                KeyCode::Backspace => {
-                                    controller.write_to_input(None);
-                                }
-                                KeyCode::Enter => {
-                                    let input_message = controller.get_input_message();
-                                    if !input_message.is_empty() {
-                                        if let Ok(_) = controller.send_message(input_message).await
-                                        {
-                                            controller.clear_input();
-                                        };
-                                    }
-                                }
-                                KeyCode::CtrlQ => {
-                                    controller.disconnect().await;
-                                }
-                                _ => {}
+            controller
+                .server_controller
+                .record_activity(&controller.app_state.user)
+                .await;
+            let before = controller.app_state.active_composer().text().to_string();
+            // Whether the decoder holds back an Escape, waiting for the rest
+            // of a sequence.
+            let mut escape_pending = false;
+            for &byte in data {
+                let keycodes = self.decoder.write(byte);
+                escape_pending = keycodes.is_empty() && (escape_pending || byte == 0x1b);
+                for keycode in keycodes {
+                    let Some(keycode) = self.translate_key(keycode) else {
+                        continue;
+                    };
+                    // The mode is checked per key so that a key switching modes
+                    // applies to the rest of the same input chunk.
+                    match controller.app_state.input_mode {
+                        InputMode::Insert => {
+                            if keycode == KeyCode::CtrlN {
+                                self.decoder = Decoder::new();
+                                self.escape_sequence = None;
                             }
+                            Self::process_insert_key(&mut controller, keycode).await;
+                        }
+                        InputMode::Navigate => {
+                            Self::process_navigate_key(&mut controller, keycode).await;
                         }
                     }
                 }
-                InputMode::Navigate => {
-                    for &byte in data {
-                        for keycode in self.decoder.write(byte) {
-                            match keycode {
-                                KeyCode::Enter => {
-                                    controller.set_mode(InputMode::Insert);
-                                }
-                                KeyCode::Char('q') => {
-                                    controller.disconnect().await;
-                                }
-                                KeyCode::Char('k') => {
-                                    controller.scroll_up(1);
-                                }
-                                KeyCode::Char('j') => {
-                                    controller.scroll_down(1);
-                                }
-                                _ => {}
-                            }
+            }
+            // Terminals send a whole sequence at once, so one still open is a
+            // lone Escape and the keys typed after it are not part of it.
+            if escape_pending {
+                self.decoder = Decoder::new();
+            }
+            self.escape_sequence = None;
+            controller.report_typing(&before).await;
+            if controller.active {
+                controller.draw()?;
+            } else {
+                drop(controller);
+                self.remove_client().await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Folds escape sequences the decoder passes through as separate keys.
+    /// Alt-Enter and Shift-Enter become Linefeed, alternative Home/End
+    /// sequences become Home/End, and any other sequence is dropped. A
+    /// sequence has to arrive in the same input chunk as its Escape.
+    fn translate_key(&mut self, keycode: KeyCode) -> Option<KeyCode> {
+        let Some(sequence) = &mut self.escape_sequence else {
+            if keycode == KeyCode::Escape {
+                self.escape_sequence = Some(String::new());
+                return None;
+            }
+            return Some(keycode);
+        };
+        match keycode {
+            KeyCode::Enter if sequence.is_empty() => {
+                self.escape_sequence = None;
+                Some(KeyCode::Linefeed)
+            }
+            KeyCode::Escape if sequence.is_empty() => None,
+            KeyCode::Char(c @ ('[' | 'O')) if sequence.is_empty() => {
+                sequence.push(c);
+                None
+            }
+            KeyCode::Char(c) if !sequence.is_empty() => {
+                sequence.push(c);
+                // Parameters are digits and ';', anything else ends the sequence.
+                if c.is_ascii_digit() || c == ';' {
+                    return None;
+                }
+                let keycode = match sequence.as_str() {
+                    "[13;2u" | "[13;3u" | "[27;2;13~" | "[27;3;13~" => Some(KeyCode::Linefeed),
+                    "OH" | "[1~" | "[7~" => Some(KeyCode::Home),
+                    "OF" | "[4~" | "[8~" => Some(KeyCode::End),
+                    _ => None,
+                };
+                self.escape_sequence = None;
+                keycode
+            }
+            // Alt with any other key acts as the key alone.
+            keycode => {
+                self.escape_sequence = None;
+                Some(keycode)
+            }
+        }
+    }
+
+    async fn process_insert_key(controller: &mut AppController, keycode: KeyCode) {
+        if !matches!(keycode, KeyCode::Tab | KeyCode::ShiftTab) {
+            controller.end_completion();
+        }
+        if controller.app_state.input_history.search().is_some() {
+            match keycode {
+                KeyCode::CtrlR => return controller.search_history(),
+                KeyCode::Char(c) => return controller.edit_history_search(Some(c)),
+                KeyCode::Space => return controller.edit_history_search(Some(' ')),
+                KeyCode::Backspace => return controller.edit_history_search(None),
+                KeyCode::CtrlG | KeyCode::CtrlC => return controller.cancel_history_search(),
+                // Any other key keeps the match and is handled as usual.
+                _ => controller.accept_history_search(),
+            }
+        }
+
+        match keycode {
+            KeyCode::CtrlN => {
+                if let Err(e) = controller.start_navigating().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char(c) => {
+                controller.write_to_input(Some(c));
+            }
+            KeyCode::Space => {
+                controller.write_to_input(Some(' '));
+            }
+            KeyCode::Backspace => {
+                controller.write_to_input(None);
+            }
+            KeyCode::Linefeed => {
+                controller.write_to_input(Some('\n'));
+            }
+            KeyCode::Delete => {
+                controller.app_state.active_composer_mut().delete();
+            }
+            KeyCode::ArrowLeft => {
+                controller.app_state.active_composer_mut().move_left();
+            }
+            KeyCode::ArrowRight => {
+                controller.app_state.active_composer_mut().move_right();
+            }
+            KeyCode::Home | KeyCode::CtrlA => {
+                controller.app_state.active_composer_mut().move_home();
+            }
+            KeyCode::End | KeyCode::CtrlE => {
+                controller.app_state.active_composer_mut().move_end();
+            }
+            KeyCode::CtrlW => {
+                controller.app_state.active_composer_mut().delete_word();
+            }
+            KeyCode::CtrlU => {
+                controller
+                    .app_state
+                    .active_composer_mut()
+                    .delete_to_line_start();
+            }
+            KeyCode::ArrowUp => {
+                controller.history_previous();
+            }
+            KeyCode::ArrowDown => {
+                controller.history_next();
+            }
+            KeyCode::CtrlR => {
+                controller.search_history();
+            }
+            KeyCode::Tab => {
+                controller.complete(false).await;
+            }
+            KeyCode::ShiftTab => {
+                controller.complete(true).await;
+            }
+            KeyCode::Enter => {
+                let input_message = controller.get_input_message();
+                if input_message.starts_with('/') {
+                    controller.record_input(&input_message).await;
+                    if let Err(e) = controller.run_command(&input_message).await {
+                        controller.notify(e.to_string());
+                    }
+                    controller.clear_input();
+                } else if !input_message.trim().is_empty() {
+                    match controller.send_message(input_message.clone()).await {
+                        Ok(()) => {
+                            controller.record_input(&input_message).await;
+                            controller.clear_input();
                         }
+                        Err(e) => controller.notify(e.to_string()),
                     }
                 }
             }
+            KeyCode::CtrlQ => {
+                controller.disconnect().await;
+            }
+            _ => {}
+        }
+    }
+
+    async fn process_navigate_key(controller: &mut AppController, keycode: KeyCode) {
+        let users_focused = controller.app_state.focus == Focus::Users;
+        let search_focused = controller.app_state.focus == Focus::Search;
+        let messages_focused = controller.app_state.focus == Focus::Messages;
+        let thread_focused = controller.app_state.thread_focused();
+        match keycode {
+            KeyCode::Enter if search_focused => {
+                if let Err(e) = controller.jump_to_selected_result().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('k') if search_focused => {
+                controller.select_previous_result();
+            }
+            KeyCode::Char('j') if search_focused => {
+                controller.select_next_result();
+            }
+            KeyCode::Char('x') if search_focused => {
+                controller.close_search();
+            }
+            KeyCode::Char('x') if thread_focused => {
+                controller.close_thread();
+            }
+            KeyCode::Enter if users_focused => {
+                if let Err(e) = controller.open_selected_direct_channel().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Enter => {
+                controller.app_state.selected_message = None;
+                controller.set_mode(InputMode::Insert);
+            }
+            KeyCode::Tab => {
+                controller.toggle_focus();
+            }
+            KeyCode::Char('q') => {
+                controller.disconnect().await;
+            }
+            KeyCode::Char('k') if users_focused => {
+                controller.select_previous_user();
+            }
+            KeyCode::Char('j') if users_focused => {
+                controller.select_next_user();
+            }
+            KeyCode::Char('k') | KeyCode::ArrowUp if messages_focused => {
+                if let Err(e) = controller.select_previous_message(1).await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('j') | KeyCode::ArrowDown if messages_focused => {
+                if let Err(e) = controller.select_next_message(1).await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::CtrlU if messages_focused => {
+                let count = controller.half_page();
+                if let Err(e) = controller.select_previous_message(count).await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::CtrlD if messages_focused => {
+                let count = controller.half_page();
+                if let Err(e) = controller.select_next_message(count).await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('g') if messages_focused => {
+                if let Err(e) = controller.select_first_message().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('G') if messages_focused => {
+                if let Err(e) = controller.select_last_message().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('r') if messages_focused => {
+                if let Err(e) = controller.open_selected_thread().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('+') if messages_focused => {
+                if let Err(e) = controller.react_to_selected_message().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('e') if messages_focused => {
+                if let Err(e) = controller.edit_selected_message() {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('d') if messages_focused => {
+                if let Err(e) = controller.delete_selected_message() {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('y') if messages_focused => {
+                if let Err(e) = controller.copy_selected_message() {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('k') => {
+                controller.scroll_up(1);
+            }
+            KeyCode::Char('j') => {
+                controller.scroll_down(1);
+            }
+            KeyCode::Char('h') => {
+                if let Err(e) = controller.previous_channel().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('u') => {
+                controller.jump_to_first_unread();
+            }
+            KeyCode::Char('m') => {
+                if let Err(e) = controller.show_mentions().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            KeyCode::Char('l') => {
+                if let Err(e) = controller.next_channel().await {
+                    controller.notify(e.to_string());
+                }
+            }
+            _ => {}
         }
-        Ok(())
     }
 }
@@ -2,100 +2,1042 @@ use ratatui::{
     buffer::Buffer,
     layout::Rect,
     layout::{Constraint, Direction, Layout},
-    text::Line,
-    widgets::{Block, Borders, Paragraph, Widget},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, Paragraph, Widget},
 };
 
-use crate::db_models::{Message, User};
+use std::collections::HashMap;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+
+use crate::{
+    app::{
+        completion::Completion,
+        composer::Composer,
+        highlight::highlight,
+        input_history::InputHistory,
+        markdown::{self, Fragment},
+        wrap::{break_line, wrap_input, wrap_line},
+    },
+    db_models::{Channel, Message, Presence, Reaction, User, UserStatus, mention_ranges},
+};
+
+const DIRECT_COLOR: Color = Color::Magenta;
+const FOCUS_COLOR: Color = Color::Yellow;
+const MENTION_COLOR: Color = Color::Cyan;
+const UNREAD_COLOR: Color = Color::Red;
+const THREAD_COLOR: Color = Color::Blue;
+/// Behind inline code and code blocks.
+const CODE_BACKGROUND: Color = Color::DarkGray;
+/// Columns a tab in a code block takes.
+const TAB_WIDTH: usize = 4;
+/// Mentions of the viewer, also shown bold.
+const OWN_MENTION_COLOR: Color = Color::Yellow;
+/// Rows the input box grows to before it scrolls.
+const MAX_INPUT_ROWS: u16 = 10;
+/// Candidates the completion popup shows at once.
+const MAX_COMPLETION_ROWS: usize = 8;
 
 pub enum InputMode {
     Insert,
     Navigate,
 }
 
+/// Panel that receives j/k and Enter in navigate mode.
+#[derive(PartialEq)]
+pub enum Focus {
+    Messages,
+    Users,
+    Search,
+    Thread,
+}
+
+/// Results of the last `/search` or `/mentions`, listed in their own pane.
+pub struct SearchResults {
+    pub title: String,
+    pub messages: Vec<Message>,
+    /// Messages shown bold, such as mentions the user hadn't seen yet.
+    pub unseen: Vec<i64>,
+    pub selected: usize,
+}
+
+/// A message and its replies, shown in the thread pane.
+pub struct Thread {
+    pub parent: Message,
+    pub replies: Vec<Message>,
+    /// Input for replies, separate from the main one.
+    pub composer: Composer,
+    pub scroll_offset: u16,
+}
+
+/// A message's rows in the message pane as last rendered, along with what
+/// they were rendered from.
+struct RenderedMessage {
+    content: String,
+    sender: String,
+    edited_at: Option<DateTime<Utc>>,
+    deleted: bool,
+    lines: Vec<Line<'static>>,
+}
+
+impl RenderedMessage {
+    fn new(
+        message: &Message,
+        sender: &str,
+        is_own_name: &dyn Fn(&str) -> bool,
+        width: usize,
+    ) -> Self {
+        Self {
+            content: message.content.clone(),
+            sender: sender.to_string(),
+            edited_at: message.edited_at,
+            deleted: message.deleted,
+            lines: message_lines(message, sender, is_own_name, width)
+                .into_iter()
+                .map(owned_line)
+                .collect(),
+        }
+    }
+
+    fn is_current(&self, message: &Message, sender: &str) -> bool {
+        self.content == message.content
+            && self.sender == sender
+            && self.edited_at == message.edited_at
+            && self.deleted == message.deleted
+    }
+}
+
 pub struct AppState {
-    pub input_message: String,
+    pub composer: Composer,
+    pub input_history: InputHistory,
+    pub completion: Option<Completion>,
+    /// Nicknames set with `/nick`, by username.
+    pub nicknames: HashMap<String, String>,
+    /// Presence and status of connected users, by username.
+    pub statuses: HashMap<String, UserStatus>,
+    /// Other users typing, by channel id.
+    pub typing: HashMap<i64, Vec<String>>,
     pub messages: Vec<Message>,
+    /// Messages older than the loaded ones may exist.
+    pub older_messages: bool,
+    /// Messages newer than the loaded ones exist, after scrolling or jumping back in history.
+    pub newer_messages: bool,
+    /// Reactions to the loaded messages, by message id.
+    pub reactions: HashMap<i64, Vec<Reaction>>,
+    /// Message chosen in navigate mode.
+    pub selected_message: Option<i64>,
+    /// Name of the command prepared for the selected message, such as
+    /// `/edit`, and that message's id. Dropped when the input is sent.
+    pub command_target: Option<(String, i64)>,
     pub user: User,
     pub users: Vec<User>,
+    pub channels: Vec<Channel>,
+    pub current_channel: usize,
     pub input_mode: InputMode,
+    pub focus: Focus,
+    pub selected_user: usize,
     pub scroll_offset: u16,
+    /// Rows the message pane showed in the last render.
+    pub message_rows: usize,
+    pub search: Option<SearchResults>,
+    pub thread: Option<Thread>,
+    /// Message jumped to from the search results.
+    pub highlighted_message: Option<i64>,
+    /// Ring the terminal bell on mentions.
+    pub mention_bell: bool,
+    /// Unread messages in channels other than the current one, by channel id.
+    pub unread: HashMap<i64, usize>,
+    /// Message the "new messages" divider is drawn above.
+    pub first_unread: Option<i64>,
+    /// Scroll to the divider on the next render, once the layout is known.
+    pub jump_to_unread: bool,
+    /// Rows of the loaded messages as last rendered, by message id, since
+    /// formatting every message on every render adds up.
+    rendered_messages: HashMap<i64, RenderedMessage>,
+    /// Width, viewer's nickname and day the rendered rows are for. Each
+    /// changes how every message looks.
+    rendered_for: Option<(usize, Option<String>, NaiveDate)>,
 }
 
 impl AppState {
     pub fn new(user: User) -> Self {
         Self {
-            input_message: String::new(),
+            composer: Composer::default(),
+            input_history: InputHistory::default(),
+            completion: None,
+            nicknames: HashMap::new(),
+            statuses: HashMap::new(),
+            typing: HashMap::new(),
             messages: Vec::new(),
-            user: user,
+            older_messages: false,
+            newer_messages: false,
+            reactions: HashMap::new(),
+            selected_message: None,
+            command_target: None,
+            user,
             users: Vec::new(),
+            channels: Vec::new(),
+            current_channel: 0,
             input_mode: InputMode::Insert,
+            focus: Focus::Messages,
+            selected_user: 0,
             scroll_offset: 0,
+            message_rows: 0,
+            search: None,
+            thread: None,
+            highlighted_message: None,
+            mention_bell: true,
+            unread: HashMap::new(),
+            first_unread: None,
+            jump_to_unread: false,
+            rendered_messages: HashMap::new(),
+            rendered_for: None,
         }
     }
+
+    pub fn current_channel(&self) -> Option<&Channel> {
+        self.channels.get(self.current_channel)
+    }
+
+    /// Whether typed input goes to the thread pane.
+    pub fn thread_focused(&self) -> bool {
+        self.focus == Focus::Thread && self.thread.is_some()
+    }
+
+    /// The composer typed input goes to: the thread pane's while it has focus.
+    pub fn active_composer(&self) -> &Composer {
+        match &self.thread {
+            Some(thread) if self.focus == Focus::Thread => &thread.composer,
+            _ => &self.composer,
+        }
+    }
+
+    pub fn active_composer_mut(&mut self) -> &mut Composer {
+        match &mut self.thread {
+            Some(thread) if self.focus == Focus::Thread => &mut thread.composer,
+            _ => &mut self.composer,
+        }
+    }
+
+    /// Name shown next to messages: the nickname if one is set.
+    pub fn sender_name<'a>(&'a self, username: &'a str) -> &'a str {
+        self.nicknames
+            .get(username)
+            .map_or(username, String::as_str)
+    }
+
+    /// `@username`, followed by the nickname if one is set.
+    pub fn mention_name(&self, username: &str) -> String {
+        match self.nicknames.get(username) {
+            Some(nickname) => format!("@{} ({})", username, nickname),
+            None => format!("@{}", username),
+        }
+    }
+
+    /// Whether `name`, as written after an `@`, is the viewer's username or nickname.
+    pub fn is_own_name(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(&self.user.username)
+            || self
+                .nicknames
+                .get(&self.user.username)
+                .is_some_and(|nickname| nickname.eq_ignore_ascii_case(name))
+    }
+
+    /// "alice, bob are typing…" for others typing in the current channel.
+    fn typing_notice(&self) -> Option<String> {
+        let typers = self.typing.get(&self.current_channel()?.id)?;
+        let names: Vec<&str> = typers.iter().map(|typer| self.sender_name(typer)).collect();
+        match names.as_slice() {
+            [] => None,
+            [name] => Some(format!("{} is typing…", name)),
+            names => Some(format!("{} are typing…", names.join(", "))),
+        }
+    }
+
+    pub fn mentions_viewer(&self, message: &Message) -> bool {
+        message
+            .mentions()
+            .into_iter()
+            .any(|name| self.is_own_name(name))
+    }
+
+    /// Whether a new message should alert the viewer: it mentions them, was
+    /// sent by someone else, and is in a channel they are a member of.
+    pub fn alerts_viewer(&self, message: &Message) -> bool {
+        message.sender.username != self.user.username
+            && self
+                .channels
+                .iter()
+                .any(|channel| channel.id == message.channel_id)
+            && self.mentions_viewer(message)
+    }
 }
 
 impl Widget for &mut AppState {
     fn render(self, area: Rect, buf: &mut Buffer) {
-        let main_area = Rect::new(0, 0, area.width, area.height.saturating_sub(4));
-        let input_area = Rect::new(0, area.height.saturating_sub(4), area.width, 3);
+        // Borders and the "> " prompt take four columns.
+        let input_width = area.width.saturating_sub(4).max(1) as usize;
+        let (input_rows, (cursor_row, cursor_column)) =
+            wrap_input(self.composer.text(), self.composer.cursor(), input_width);
+        let visible_rows =
+            (input_rows.len() as u16).clamp(1, MAX_INPUT_ROWS.min(area.height / 3).max(1));
+        let input_height = visible_rows + 2;
+
+        let typing = self.typing_notice();
+        let typing_height = typing.is_some() as u16;
+        let main_area = Rect::new(
+            0,
+            0,
+            area.width,
+            area.height.saturating_sub(input_height + typing_height + 1),
+        );
+        let typing_area = Rect::new(
+            0,
+            area.height.saturating_sub(input_height + typing_height + 1),
+            area.width,
+            typing_height,
+        );
+        let input_area = Rect::new(
+            0,
+            area.height.saturating_sub(input_height + 1),
+            area.width,
+            input_height,
+        );
         let help_area = Rect::new(0, area.height.saturating_sub(1), area.width, 1);
 
         let main_layout = Layout::default()
             .direction(Direction::Horizontal)
-            .constraints(vec![Constraint::Percentage(90), Constraint::Percentage(10)])
+            .constraints(vec![Constraint::Percentage(85), Constraint::Percentage(15)])
             .split(main_area);
 
-        let message_area = main_layout[0];
-        let users_area = main_layout[1];
+        let (stream_area, thread_area) = if self.thread.is_some() {
+            let stream_layout = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(vec![Constraint::Percentage(55), Constraint::Percentage(45)])
+                .split(main_layout[0]);
+            (stream_layout[0], Some(stream_layout[1]))
+        } else {
+            (main_layout[0], None)
+        };
+
+        let (message_area, search_area) = if self.search.is_some() {
+            let message_layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![Constraint::Percentage(60), Constraint::Percentage(40)])
+                .split(stream_area);
+            (message_layout[0], Some(message_layout[1]))
+        } else {
+            (stream_area, None)
+        };
 
+        let sidebar_layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(self.channels.len() as u16 + 2),
+                Constraint::Min(0),
+            ])
+            .split(main_layout[1]);
+
+        let channels_area = sidebar_layout[0];
+        let users_area = sidebar_layout[1];
+
+        // Lines are wrapped to the pane, so that scrolling counts rows on screen.
+        let text_width = message_area.width.saturating_sub(2) as usize;
+        let mut lines: Vec<Line> = Vec::new();
+        let mut divider_line = None;
+        let mut selected_lines = None;
+        let rendered_for = Some((
+            text_width,
+            self.nicknames.get(&self.user.username).cloned(),
+            Local::now().date_naive(),
+        ));
+        let mut previous = std::mem::take(&mut self.rendered_messages);
+        if self.rendered_for != rendered_for {
+            previous.clear();
+            self.rendered_for = rendered_for;
+        }
+        let mut rendered = HashMap::new();
+        for message in &self.messages {
+            if self.first_unread == Some(message.id) {
+                divider_line = Some(lines.len());
+                lines.push(
+                    Line::styled("── new messages ──", Style::default().fg(UNREAD_COLOR))
+                        .centered(),
+                );
+            }
+            let highlighted = self.highlighted_message == Some(message.id);
+            let selected = self.selected_message == Some(message.id);
+            let sender = self.sender_name(&message.sender.username);
+            let is_own_name = |name: &str| self.is_own_name(name);
+            let start = lines.len();
+            let message_rows = match previous.remove(&message.id) {
+                Some(cached) if cached.is_current(message, sender) => cached,
+                _ => RenderedMessage::new(message, sender, &is_own_name, text_width),
+            };
+            lines.extend(message_rows.lines.iter().cloned().map(|line| {
+                if selected {
+                    line.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else if highlighted {
+                    line.style(
+                        Style::default()
+                            .fg(FOCUS_COLOR)
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    line
+                }
+            }));
+            if let Some(reactions) = self.reactions.get(&message.id) {
+                lines.extend(wrap_line(
+                    reaction_line(reactions, &self.user),
+                    text_width,
+                    2,
+                ));
+            }
+            if message.reply_count > 0 {
+                lines.push(Line::styled(
+                    format!(
+                        "  ↳ {} {}",
+                        message.reply_count,
+                        if message.reply_count == 1 {
+                            "reply"
+                        } else {
+                            "replies"
+                        }
+                    ),
+                    Style::default().fg(THREAD_COLOR),
+                ));
+            }
+            if selected {
+                selected_lines = Some((start, lines.len()));
+            }
+            rendered.insert(message.id, message_rows);
+        }
+        self.rendered_messages = rendered;
+
+        let message_rows = message_area.height.saturating_sub(2) as usize;
+        self.message_rows = message_rows;
+        if let (true, Some(divider_line)) = (std::mem::take(&mut self.jump_to_unread), divider_line)
+        {
+            // Put the divider at the top of the pane.
+            self.scroll_offset = lines.len().saturating_sub(divider_line + message_rows) as u16;
+        }
+        if let Some((start, end)) = selected_lines {
+            // Keep the selected message on screen.
+            let bottom = lines.len().saturating_sub(self.scroll_offset as usize);
+            if end > bottom {
+                self.scroll_offset = lines.len().saturating_sub(end) as u16;
+            } else if start < bottom.saturating_sub(message_rows) {
+                self.scroll_offset = lines.len().saturating_sub(start + message_rows) as u16;
+            }
+        }
         self.scroll_offset = self
             .scroll_offset
-            .min((self.messages.len() as isize - message_area.height as isize + 2).max(0) as u16);
+            .min(lines.len().saturating_sub(message_rows) as u16);
 
-        let message_list: Vec<Line> = self
-            .messages
-            .iter()
-            .map(|message| {
-                Line::raw(format!(
-                    "{sender}: {message}",
-                    message = message.content,
-                    sender = message.sender.username
-                ))
-            })
+        let message_list: Vec<Line> = lines
+            .into_iter()
             .rev()
             .skip(self.scroll_offset as usize)
-            .take(message_area.height.saturating_sub(2) as usize)
+            .take(message_rows)
             .rev()
             .collect();
 
+        let channel_list: Vec<Line> = self
+            .channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| {
+                let mut style = Style::default();
+                if channel.direct {
+                    style = style.fg(DIRECT_COLOR);
+                }
+                if index == self.current_channel {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                match self.unread.get(&channel.id) {
+                    Some(&unread) if unread > 0 => Line::styled(
+                        format!("{} ({})", channel.display_name(&self.user), unread),
+                        style.add_modifier(Modifier::BOLD),
+                    ),
+                    _ => Line::styled(channel.display_name(&self.user), style),
+                }
+            })
+            .collect();
+
+        let navigating = matches!(self.input_mode, InputMode::Navigate);
+        let users_focused = navigating && self.focus == Focus::Users;
+        let search_focused = navigating && self.focus == Focus::Search;
+
         let user_list: Vec<Line> = self
             .users
             .iter()
-            .map(|user| Line::raw(format!("@{}", user.username)))
+            .enumerate()
+            .map(|(index, user)| {
+                let status = self.statuses.get(&user.username);
+                let (marker, color) = match status.map(|status| status.presence) {
+                    Some(Presence::Online) | None => ("●", Color::Green),
+                    Some(Presence::Idle) => ("◐", Color::Yellow),
+                    Some(Presence::Away) => ("○", Color::DarkGray),
+                };
+                let mut line = Line::from(vec![
+                    Span::styled(format!("{} ", marker), Style::default().fg(color)),
+                    Span::raw(self.mention_name(&user.username)),
+                ]);
+                if let Some(text) = status.and_then(|status| status.text.as_ref()) {
+                    line.push_span(Span::styled(
+                        format!(" {}", text),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                if users_focused && index == self.selected_user {
+                    line.style(Style::default().add_modifier(Modifier::REVERSED))
+                } else {
+                    line
+                }
+            })
             .collect();
 
+        let channel_title = self
+            .current_channel()
+            .map(|channel| {
+                let mut title = if channel.direct {
+                    Line::styled(
+                        format!("Direct: {}", channel.display_name(&self.user)),
+                        Style::default().fg(DIRECT_COLOR),
+                    )
+                } else {
+                    Line::raw(channel.display_name(&self.user))
+                };
+                if let Some(topic) = &channel.topic {
+                    title.push_span(Span::styled(
+                        format!(" {}", topic),
+                        Style::default().fg(Color::DarkGray),
+                    ));
+                }
+                title
+            })
+            .unwrap_or_default();
+
         Paragraph::new(message_list)
-            .block(Block::new().borders(Borders::ALL))
+            .block(Block::new().borders(Borders::ALL).title(channel_title))
             .render(message_area, buf);
 
+        Paragraph::new(channel_list)
+            .block(Block::new().borders(Borders::ALL).title("Channels"))
+            .render(channels_area, buf);
+
+        let users_block = if users_focused {
+            Block::new()
+                .borders(Borders::ALL)
+                .border_style(Style::default().fg(FOCUS_COLOR))
+        } else {
+            Block::new().borders(Borders::ALL)
+        };
+
         Paragraph::new(user_list)
-            .block(Block::new().borders(Borders::ALL))
+            .block(users_block.title("Users"))
             .render(users_area, buf);
 
+        if let (Some(search), Some(search_area)) = (&self.search, search_area) {
+            let height = search_area.height.saturating_sub(2) as usize;
+            // Keep the selected result on screen.
+            let skip = (search.selected + 1).saturating_sub(height);
+            let result_list: Vec<Line> = search
+                .messages
+                .iter()
+                .enumerate()
+                .skip(skip)
+                .take(height)
+                .map(|(index, message)| {
+                    let channel = self.channels.iter().find(|c| c.id == message.channel_id);
+                    let sender = self.sender_name(&message.sender.username);
+                    let mut line = search_result_line(message, channel, &self.user, sender);
+                    if search.unseen.contains(&message.id) {
+                        line = line.style(Style::default().add_modifier(Modifier::BOLD));
+                    }
+                    if search_focused && index == search.selected {
+                        line.style(Style::default().add_modifier(Modifier::REVERSED))
+                    } else {
+                        line
+                    }
+                })
+                .collect();
+
+            let mut search_block = Block::new()
+                .borders(Borders::ALL)
+                .title(search.title.as_str());
+            if search_focused {
+                search_block = search_block.border_style(Style::default().fg(FOCUS_COLOR));
+            }
+            Paragraph::new(result_list)
+                .block(search_block)
+                .render(search_area, buf);
+        }
+
+        let mut completion_anchor = input_area;
+        let thread_focused = self.thread_focused();
+        let mut thread_offset = None;
+        if let (Some(thread), Some(thread_area)) = (&self.thread, thread_area) {
+            let input_width = thread_area.width.saturating_sub(4).max(1) as usize;
+            let (input_rows, cursor) = wrap_input(
+                thread.composer.text(),
+                thread.composer.cursor(),
+                input_width,
+            );
+            let visible_rows = (input_rows.len() as u16)
+                .clamp(1, MAX_INPUT_ROWS.min(thread_area.height / 3).max(1));
+            let thread_layout = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![
+                    Constraint::Min(0),
+                    Constraint::Length(visible_rows + 2),
+                ])
+                .split(thread_area);
+            completion_anchor = thread_layout[1];
+
+            let is_own_name = |name: &str| self.is_own_name(name);
+            let text_width = thread_area.width.saturating_sub(2) as usize;
+            let mut lines: Vec<Line> = Vec::new();
+            for message in std::iter::once(&thread.parent).chain(&thread.replies) {
+                let sender = self.sender_name(&message.sender.username);
+                lines.extend(message_lines(message, sender, &is_own_name, text_width));
+                if let Some(reactions) = self.reactions.get(&message.id) {
+                    lines.extend(wrap_line(
+                        reaction_line(reactions, &self.user),
+                        text_width,
+                        2,
+                    ));
+                }
+                if message.id == thread.parent.id {
+                    let count = thread.replies.iter().filter(|reply| !reply.deleted).count();
+                    lines.push(
+                        Line::styled(
+                            format!(
+                                "── {} {} ──",
+                                count,
+                                if count == 1 { "reply" } else { "replies" }
+                            ),
+                            Style::default().fg(THREAD_COLOR),
+                        )
+                        .centered(),
+                    );
+                }
+            }
+            let rows = thread_layout[0].height.saturating_sub(2) as usize;
+            let offset = thread
+                .scroll_offset
+                .min(lines.len().saturating_sub(rows) as u16);
+            thread_offset = Some(offset);
+            let thread_list: Vec<Line> = lines
+                .into_iter()
+                .rev()
+                .skip(offset as usize)
+                .take(rows)
+                .rev()
+                .collect();
+
+            let mut thread_block = Block::new().borders(Borders::ALL).title("Thread");
+            if thread_focused {
+                thread_block = thread_block.border_style(Style::default().fg(FOCUS_COLOR));
+            }
+            Paragraph::new(thread_list)
+                .block(thread_block)
+                .render(thread_layout[0], buf);
+
+            let mut reply_block = Block::new().borders(Borders::ALL).title("Reply");
+            if thread_focused {
+                reply_block = reply_block.border_style(Style::default().fg(FOCUS_COLOR));
+            }
+            let reply_lines = if let InputMode::Insert = self.input_mode {
+                input_lines(&input_rows, cursor, visible_rows as usize, thread_focused)
+            } else {
+                Vec::new()
+            };
+            Paragraph::new(reply_lines)
+                .block(reply_block)
+                .render(thread_layout[1], buf);
+        }
+        if let (Some(thread), Some(offset)) = (&mut self.thread, thread_offset) {
+            thread.scroll_offset = offset;
+        }
+
+        if let (InputMode::Insert, Some(completion)) = (&self.input_mode, &self.completion) {
+            let rows = completion.candidates.len().min(MAX_COMPLETION_ROWS);
+            // Keep the selected candidate on screen.
+            let skip = (completion.selected + 1).saturating_sub(rows);
+            let completion_list: Vec<Line> = completion
+                .candidates
+                .iter()
+                .enumerate()
+                .skip(skip)
+                .take(rows)
+                .map(|(index, candidate)| {
+                    let line = Line::raw(candidate.label.as_str());
+                    if index == completion.selected {
+                        line.style(Style::default().add_modifier(Modifier::REVERSED))
+                    } else {
+                        line
+                    }
+                })
+                .collect();
+            let width = completion_list
+                .iter()
+                .map(Line::width)
+                .max()
+                .unwrap_or_default() as u16
+                + 2;
+            let height = rows as u16 + 2;
+            let popup_area = Rect::new(
+                completion_anchor.x + 1,
+                completion_anchor.y.saturating_sub(height),
+                width.min(area.width.saturating_sub(completion_anchor.x + 1)),
+                height.min(completion_anchor.y),
+            );
+            Clear.render(popup_area, buf);
+            Paragraph::new(completion_list)
+                .block(
+                    Block::new()
+                        .borders(Borders::ALL)
+                        .border_style(Style::default().fg(FOCUS_COLOR)),
+                )
+                .render(popup_area, buf);
+        }
+
+        if let Some(typing) = typing {
+            Paragraph::new(Line::styled(
+                format!(" {}", typing),
+                Style::default()
+                    .fg(Color::DarkGray)
+                    .add_modifier(Modifier::ITALIC),
+            ))
+            .render(typing_area, buf);
+        }
+
         if let InputMode::Insert = self.input_mode {
-            Paragraph::new(format!("> {}▉", self.input_message))
-                .block(Block::new().borders(Borders::ALL))
+            let input_list = input_lines(
+                &input_rows,
+                (cursor_row, cursor_column),
+                visible_rows as usize,
+                !thread_focused,
+            );
+            let mut input_block = Block::new().borders(Borders::ALL);
+            if let Some(search) = self.input_history.search() {
+                let failing = if search.found.is_none() && !search.query.is_empty() {
+                    "failing "
+                } else {
+                    ""
+                };
+                input_block = input_block
+                    .title(format!("{}reverse search: {}", failing, search.query))
+                    .border_style(Style::default().fg(FOCUS_COLOR));
+            }
+            Paragraph::new(input_list)
+                .block(input_block)
                 .render(input_area, buf);
         }
 
         match self.input_mode {
             InputMode::Insert => {
-                Paragraph::new("Ctrl-N: navigate mode | Ctrl-Q: exit").render(help_area, buf);
+                let help = if self.input_history.search().is_some() {
+                    "Ctrl-R: older match | Ctrl-G: cancel | Enter: send match | other keys: edit match"
+                } else if thread_focused {
+                    "Enter: reply | Alt-Enter: new line | Up/Down/Ctrl-R: history | Ctrl-N: navigate mode | Ctrl-Q: exit"
+                } else {
+                    "Enter: send | Alt-Enter: new line | Up/Down/Ctrl-R: history | Ctrl-N: navigate mode | Ctrl-Q: exit"
+                };
+                Paragraph::new(help).render(help_area, buf);
             }
             InputMode::Navigate => {
-                Paragraph::new(format!("Enter: exit navigate mode | k: scroll up | j: scroll down | q: exit | offset: {}", self.scroll_offset)).render(help_area, buf);
+                let help = match self.focus {
+                    Focus::Messages => "j/k/g/G/^D/^U: move | r: reply | +: react | e: edit | y: copy | d: delete | h/l: channel | Tab: users | Enter: type | q: exit".to_string(),
+                    Focus::Users => "Enter: direct message | k: previous user | j: next user | Tab: next pane | q: exit".to_string(),
+                    Focus::Search => "Enter: jump to message | k: previous result | j: next result | x: close | Tab: next pane | q: exit".to_string(),
+                    Focus::Thread => "Enter: write a reply | j/k: scroll | x: close thread | Tab: messages | q: exit".to_string(),
+                };
+                Paragraph::new(help).render(help_area, buf);
+            }
+        }
+    }
+}
+
+/// When a message was sent, followed by a space. Messages stored before
+/// sshlack kept timestamps have a time of 0 and show none.
+fn format_time(time: &DateTime<Utc>) -> String {
+    if time.timestamp() == 0 {
+        return String::new();
+    }
+    let time = time.with_timezone(&Local);
+    if time.date_naive() == Local::now().date_naive() {
+        time.format("%H:%M ").to_string()
+    } else {
+        time.format("%b %d %H:%M ").to_string()
+    }
+}
+
+/// A message as rows of at most `width` cells, with its Markdown formatted.
+/// Text wraps between words, code blocks break anywhere so that their
+/// whitespace is kept.
+fn message_lines<'a>(
+    message: &'a Message,
+    sender: &str,
+    is_own_name: &dyn Fn(&str) -> bool,
+    width: usize,
+) -> Vec<Line<'a>> {
+    let muted = Style::default().fg(Color::DarkGray);
+    let mut spans = vec![Span::styled(format_time(&message.created_at), muted)];
+    if message.deleted {
+        spans.push(Span::raw(format!("{}: ", sender)));
+        spans.push(Span::styled(
+            "message deleted",
+            muted.add_modifier(Modifier::ITALIC),
+        ));
+        return wrap_line(Line::from(spans), width, 2);
+    }
+
+    let (content, style) = match message.action() {
+        Some(action) => {
+            spans.push(Span::styled(
+                format!("* {} ", sender),
+                Style::default().add_modifier(Modifier::ITALIC),
+            ));
+            (action, Style::default().add_modifier(Modifier::ITALIC))
+        }
+        None => {
+            spans.push(Span::raw(format!("{}: ", sender)));
+            (message.content.as_str(), Style::default())
+        }
+    };
+    // The first line of text follows the sender, quotes and code start below it.
+    let mut header = Some(spans);
+    // Lines before wrapping, flagged if they are code.
+    let mut lines: Vec<(Line, bool)> = Vec::new();
+    for block in markdown::parse(content) {
+        match block {
+            markdown::Block::Text(fragments) => {
+                let mut spans = header.take().unwrap_or_else(|| vec![Span::raw("  ")]);
+                spans.extend(fragment_spans(&fragments, style, is_own_name));
+                lines.push((Line::from(spans), false));
+            }
+            markdown::Block::Quote(fragments) => {
+                lines.extend(header.take().map(|spans| (Line::from(spans), false)));
+                let mut spans = vec![Span::raw("  "), Span::styled("▎ ", muted)];
+                spans.extend(fragment_spans(&fragments, style, is_own_name));
+                lines.push((Line::from(spans), false));
+            }
+            markdown::Block::Code {
+                language,
+                lines: code,
+            } => {
+                lines.extend(header.take().map(|spans| (Line::from(spans), false)));
+                let code: Vec<String> = code
+                    .iter()
+                    .map(|line| line.replace('\t', &" ".repeat(TAB_WIDTH)))
+                    .collect();
+                let highlighted = language.and_then(|language| highlight(&code, language));
+                for (index, line) in code.into_iter().enumerate() {
+                    let mut spans = vec![Span::raw("  ")];
+                    match &highlighted {
+                        Some(highlighted) => spans.extend(
+                            highlighted[index]
+                                .iter()
+                                .map(|span| span.clone().patch_style(code_style())),
+                        ),
+                        None => spans.push(Span::styled(line, code_style())),
+                    }
+                    lines.push((Line::from(spans), true));
+                }
             }
         }
     }
+    lines.extend(header.map(|spans| (Line::from(spans), false)));
+    if message.edited_at.is_some() {
+        let edited = Span::styled(" (edited)", muted);
+        match lines.last_mut() {
+            Some((last, false)) => last.push_span(edited),
+            _ => lines.push((Line::from(vec![Span::raw(" "), edited]), false)),
+        }
+    }
+
+    lines
+        .into_iter()
+        .flat_map(|(line, code)| {
+            if !code {
+                return wrap_line(line, width, 2);
+            }
+            // Code rows are padded so that the block has an even background.
+            break_line(line, width, 2)
+                .into_iter()
+                .map(|mut row| {
+                    let padding = width.saturating_sub(row.width());
+                    row.push_span(Span::styled(" ".repeat(padding), code_style()));
+                    row
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// `line` with its text copied, so that it can be kept between renders.
+fn owned_line(line: Line) -> Line<'static> {
+    Line {
+        spans: line
+            .spans
+            .into_iter()
+            .map(|span| Span::styled(span.content.into_owned(), span.style))
+            .collect(),
+        style: line.style,
+        alignment: line.alignment,
+    }
+}
+
+fn code_style() -> Style {
+    Style::default().bg(CODE_BACKGROUND)
+}
+
+/// Spans for formatted text, with `@mentions` coloured outside of code.
+fn fragment_spans<'a>(
+    fragments: &[Fragment<'a>],
+    style: Style,
+    is_own_name: &dyn Fn(&str) -> bool,
+) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    for fragment in fragments {
+        let mut fragment_style = style;
+        if fragment.bold {
+            fragment_style = fragment_style.add_modifier(Modifier::BOLD);
+        }
+        if fragment.italic {
+            fragment_style = fragment_style.add_modifier(Modifier::ITALIC);
+        }
+        if fragment.code {
+            spans.push(Span::styled(
+                fragment.text,
+                fragment_style.patch(code_style()),
+            ));
+        } else {
+            spans.extend(mention_spans(fragment.text, fragment_style, is_own_name));
+        }
+    }
+    spans
+}
+
+/// Reaction counts shown under a message, e.g. `👍 2  🎉 1`, with the
+/// viewer's own reactions highlighted.
+fn reaction_line<'a>(reactions: &[Reaction], viewer: &User) -> Line<'a> {
+    let mut counts: Vec<(&str, usize, bool)> = Vec::new();
+    for reaction in reactions {
+        let own = reaction.username == viewer.username;
+        match counts
+            .iter_mut()
+            .find(|(emoji, _, _)| *emoji == reaction.emoji)
+        {
+            Some((_, count, reacted)) => {
+                *count += 1;
+                *reacted |= own;
+            }
+            None => counts.push((&reaction.emoji, 1, own)),
+        }
+    }
+    let mut spans = vec![Span::raw("  ")];
+    for (emoji, count, reacted) in counts {
+        let style = if reacted {
+            Style::default().fg(FOCUS_COLOR)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        spans.push(Span::styled(format!("{} {}  ", emoji, count), style));
+    }
+    Line::from(spans)
+}
+
+/// Splits `text` into spans with its `@mentions` coloured, and mentions of
+/// the viewer in bold.
+fn mention_spans<'a>(
+    text: &'a str,
+    style: Style,
+    is_own_name: &dyn Fn(&str) -> bool,
+) -> Vec<Span<'a>> {
+    let mut spans = Vec::new();
+    let mut end = 0;
+    for (start, mention_end) in mention_ranges(text) {
+        spans.push(Span::styled(&text[end..start], style));
+        let mention_style = if is_own_name(&text[start + 1..mention_end]) {
+            style.fg(OWN_MENTION_COLOR).add_modifier(Modifier::BOLD)
+        } else {
+            style.fg(MENTION_COLOR)
+        };
+        spans.push(Span::styled(&text[start..mention_end], mention_style));
+        end = mention_end;
+    }
+    spans.push(Span::styled(&text[end..], style));
+    spans
+}
+
+/// Input rows as shown in an input box, scrolled to keep the cursor row on
+/// screen. The cursor is drawn only if `show_cursor`.
+fn input_lines(
+    rows: &[String],
+    (cursor_row, cursor_column): (usize, usize),
+    visible_rows: usize,
+    show_cursor: bool,
+) -> Vec<Line<'_>> {
+    let skip = (cursor_row + 1).saturating_sub(visible_rows);
+    rows.iter()
+        .enumerate()
+        .skip(skip)
+        .take(visible_rows)
+        .map(|(index, row)| {
+            let prompt = if index == 0 { "> " } else { "  " };
+            if index != cursor_row || !show_cursor {
+                return Line::from(vec![Span::raw(prompt), Span::raw(row.as_str())]);
+            }
+            let (before, rest) = row.split_at(
+                row.char_indices()
+                    .nth(cursor_column)
+                    .map_or(row.len(), |(i, _)| i),
+            );
+            let mut after = rest.chars();
+            let under_cursor = after.next().unwrap_or(' ');
+            Line::from(vec![
+                Span::raw(prompt),
+                Span::raw(before),
+                Span::styled(
+                    under_cursor.to_string(),
+                    Style::default().add_modifier(Modifier::REVERSED),
+                ),
+                Span::raw(after.as_str()),
+            ])
+        })
+        .collect()
+}
+
+fn search_result_line<'a>(
+    message: &'a Message,
+    channel: Option<&Channel>,
+    viewer: &User,
+    sender: &str,
+) -> Line<'a> {
+    let muted = Style::default().fg(Color::DarkGray);
+    let channel_span = match channel {
+        Some(channel) if channel.direct => Span::styled(
+            format!("{} ", channel.display_name(viewer)),
+            Style::default().fg(DIRECT_COLOR),
+        ),
+        Some(channel) => Span::styled(format!("{} ", channel.display_name(viewer)), muted),
+        None => Span::raw(""),
+    };
+    Line::from(vec![
+        Span::styled(format_time(&message.created_at), muted),
+        channel_span,
+        match message.action() {
+            Some(action) => Span::raw(format!("* {} {}", sender, action.replace('\n', " "))),
+            None => Span::raw(format!(
+                "{}: {}",
+                sender,
+                message.content.replace('\n', " ")
+            )),
+        },
+    ])
 }
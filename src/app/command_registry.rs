@@ -0,0 +1,81 @@
+use std::{future::Future, pin::Pin};
+
+use crate::app::AppController;
+
+pub type CommandFuture<'a> = Pin<Box<dyn Future<Output = Result<(), anyhow::Error>> + Send + 'a>>;
+
+/// Runs a command. Errors are shown to the user in the message pane.
+pub type CommandHandler = for<'a> fn(&'a mut AppController, CommandArgs<'a>) -> CommandFuture<'a>;
+
+pub struct Command {
+    /// Name including the slash, e.g. `/join`.
+    pub name: &'static str,
+    /// Arguments, e.g. `#channel`, shown in `/help` and usage errors.
+    pub usage: &'static str,
+    pub description: &'static str,
+    pub handler: CommandHandler,
+}
+
+/// What was typed after the command name.
+pub struct CommandArgs<'a> {
+    /// The arguments as typed, trimmed.
+    pub text: &'a str,
+    /// The arguments split on whitespace.
+    pub words: Vec<&'a str>,
+    command: &'a Command,
+}
+
+impl CommandArgs<'_> {
+    /// Error explaining how to call the command.
+    pub fn usage_error(&self) -> anyhow::Error {
+        anyhow::anyhow!("Usage: {} {}", self.command.name, self.command.usage)
+    }
+}
+
+/// Slash commands by name. Feature modules add theirs with a `register`
+/// function calling [`CommandRegistry::register`], and the server is given
+/// the finished registry.
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Command>,
+}
+
+impl CommandRegistry {
+    /// Adds a command, replacing any with the same name.
+    pub fn register(&mut self, command: Command) {
+        self.commands
+            .retain(|existing| existing.name != command.name);
+        self.commands.push(command);
+        self.commands.sort_by_key(|command| command.name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| command.name == name)
+    }
+
+    /// All commands, sorted by name.
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    /// Splits `input` such as `/join #ops` into its command and arguments.
+    pub fn parse<'a>(
+        &'a self,
+        input: &'a str,
+    ) -> Result<(&'a Command, CommandArgs<'a>), anyhow::Error> {
+        let input = input.trim();
+        let (name, text) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let command = self
+            .get(&name.to_lowercase())
+            .ok_or_else(|| anyhow::anyhow!("Unknown command {}, see /help", name))?;
+        let text = text.trim();
+        Ok((
+            command,
+            CommandArgs {
+                text,
+                words: text.split_whitespace().collect(),
+                command,
+            },
+        ))
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::LazyLock;
+
+use emojic::{grouped::base_emojis, parse_alias};
+
+/// Most candidates a completion offers.
+pub const COMPLETION_LIMIT: usize = 50;
+
+/// Emoji shortcodes with their graphemes, sorted by shortcode. emojic accepts
+/// every emoji's name in snake case, e.g. `:thumbs_up:`.
+static EMOJI: LazyLock<Vec<(String, &'static str)>> = LazyLock::new(|| {
+    let mut emoji: Vec<(String, &'static str)> = base_emojis()
+        .filter_map(|emoji| {
+            let shortcode = emoji
+                .name
+                .to_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("_");
+            parse_alias(&format!(":{}:", shortcode)).map(|parsed| (shortcode, parsed.grapheme))
+        })
+        .collect();
+    emoji.sort();
+    emoji.dedup_by(|a, b| a.0 == b.0);
+    emoji
+});
+
+pub struct Candidate {
+    /// Shown in the popup.
+    pub label: String,
+    /// Replaces the word being completed.
+    pub replacement: String,
+}
+
+impl Candidate {
+    pub fn new(text: String) -> Self {
+        Self {
+            label: text.clone(),
+            replacement: text,
+        }
+    }
+}
+
+/// Candidates offered by Tab for the word before the cursor. Tab and
+/// Shift-Tab cycle through them, any other key keeps the current one.
+pub struct Completion {
+    /// Where the completed word starts in the input.
+    pub start: usize,
+    pub candidates: Vec<Candidate>,
+    pub selected: usize,
+}
+
+/// Start of the word ending at `cursor`.
+pub fn word_start(text: &str, cursor: usize) -> usize {
+    text[..cursor]
+        .char_indices()
+        .rfind(|(_, c)| c.is_whitespace())
+        .map_or(0, |(index, c)| index + c.len_utf8())
+}
+
+/// The emoji for `:shortcode:` or `shortcode`, or `text` itself if it is an emoji.
+pub fn parse_emoji(text: &str) -> Option<&'static str> {
+    parse_alias(&format!(":{}:", text.trim_matches(':')))
+        .map(|emoji| emoji.grapheme)
+        .or_else(|| {
+            EMOJI
+                .iter()
+                .find(|(_, grapheme)| *grapheme == text)
+                .map(|(_, grapheme)| *grapheme)
+        })
+}
+
+/// Emoji whose shortcode starts with `prefix`, completed to the emoji itself.
+pub fn emoji_candidates(prefix: &str) -> Vec<Candidate> {
+    EMOJI
+        .iter()
+        .filter(|(shortcode, _)| shortcode.starts_with(prefix))
+        .take(COMPLETION_LIMIT)
+        .map(|(shortcode, grapheme)| Candidate {
+            label: format!("{} :{}:", grapheme, shortcode),
+            replacement: grapheme.to_string(),
+        })
+        .collect()
+}
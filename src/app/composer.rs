@@ -0,0 +1,165 @@
+/// Text being typed in insert mode, with a cursor. Positions are byte
+/// offsets into `text` and always sit on a char boundary.
+#[derive(Default)]
+pub struct Composer {
+    text: String,
+    cursor: usize,
+}
+
+impl Composer {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Replaces the text and puts the cursor at its end.
+    pub fn set_text(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn backspace(&mut self) {
+        if let Some(start) = self.previous_boundary() {
+            self.text.replace_range(start..self.cursor, "");
+            self.cursor = start;
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if let Some(end) = self.next_boundary() {
+            self.text.replace_range(self.cursor..end, "");
+        }
+    }
+
+    pub fn move_left(&mut self) {
+        if let Some(start) = self.previous_boundary() {
+            self.cursor = start;
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if let Some(end) = self.next_boundary() {
+            self.cursor = end;
+        }
+    }
+
+    /// Moves to the start of the current line.
+    pub fn move_home(&mut self) {
+        self.cursor = self.line_start();
+    }
+
+    /// Moves to the end of the current line.
+    pub fn move_end(&mut self) {
+        self.cursor = self.text[self.cursor..]
+            .find('\n')
+            .map_or(self.text.len(), |offset| self.cursor + offset);
+    }
+
+    /// Moves to the same column on the previous line. Returns false on the first line.
+    pub fn move_up(&mut self) -> bool {
+        let start = self.line_start();
+        if start == 0 {
+            return false;
+        }
+        let column = self.text[start..self.cursor].chars().count();
+        let previous_start = self.text[..start - 1]
+            .rfind('\n')
+            .map_or(0, |index| index + 1);
+        self.cursor = self.column_offset(previous_start, start - 1, column);
+        true
+    }
+
+    /// Moves to the same column on the next line. Returns false on the last line.
+    pub fn move_down(&mut self) -> bool {
+        let Some(end) = self.text[self.cursor..].find('\n') else {
+            return false;
+        };
+        let column = self.text[self.line_start()..self.cursor].chars().count();
+        let next_start = self.cursor + end + 1;
+        let next_end = self.text[next_start..]
+            .find('\n')
+            .map_or(self.text.len(), |offset| next_start + offset);
+        self.cursor = self.column_offset(next_start, next_end, column);
+        true
+    }
+
+    /// Deletes the word before the cursor, along with whitespace after it (Ctrl-W).
+    pub fn delete_word(&mut self) {
+        let before = &self.text[..self.cursor];
+        let word_end = before.trim_end().len();
+        let start = before[..word_end]
+            .rfind(char::is_whitespace)
+            .map_or(0, |index| {
+                index + before[index..].chars().next().map_or(1, char::len_utf8)
+            });
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Deletes from the start of the current line to the cursor (Ctrl-U).
+    pub fn delete_to_line_start(&mut self) {
+        let start = self.line_start();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Replaces the text between `start` and the cursor, keeping the cursor
+    /// right after the replacement.
+    pub fn replace_from(&mut self, start: usize, replacement: &str) {
+        self.text.replace_range(start..self.cursor, replacement);
+        self.cursor = start + replacement.len();
+    }
+
+    /// Rewrites the text before the cursor, keeping the cursor right after it.
+    pub fn replace_before_cursor(&mut self, replace: impl FnOnce(&str) -> String) {
+        let before = replace(&self.text[..self.cursor]);
+        self.text.replace_range(..self.cursor, &before);
+        self.cursor = before.len();
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor]
+            .rfind('\n')
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Offset of the char at `column` in the line between `start` and `end`,
+    /// or `end` if the line is shorter.
+    fn column_offset(&self, start: usize, end: usize, column: usize) -> usize {
+        self.text[start..end]
+            .char_indices()
+            .nth(column)
+            .map_or(end, |(index, _)| start + index)
+    }
+
+    fn previous_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(index, _)| index)
+    }
+
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map(|c| self.cursor + c.len_utf8())
+    }
+}
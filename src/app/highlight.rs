@@ -0,0 +1,113 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{self, FontStyle, Theme},
+    parsing::SyntaxSet,
+};
+
+use two_face::theme::EmbeddedThemeName;
+
+/// Code blocks kept highlighted, shared by all clients.
+const CACHE_SIZE: usize = 256;
+
+/// Longest code block highlighted, in lines and bytes. Highlighting runs
+/// while the screen is drawn, so longer blocks are shown plain.
+const MAX_LINES: usize = 200;
+const MAX_BYTES: usize = 16 * 1024;
+
+/// Grammars bundled with the binary, loaded on first use.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(two_face::syntax::extra_no_newlines);
+
+/// Colours from the terminal's own palette, like the rest of the interface.
+static THEME: LazyLock<Theme> = LazyLock::new(|| {
+    two_face::theme::extra()
+        .get(EmbeddedThemeName::Ansi)
+        .clone()
+});
+
+/// Highlighted code blocks by language and code, as every render highlights
+/// every code block on screen.
+static CACHE: LazyLock<Mutex<Cache>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Spans for each line of a code block.
+pub type HighlightedCode = Vec<Vec<Span<'static>>>;
+
+/// Code blocks highlighted so far, by language and code.
+type Cache = HashMap<(String, String), Arc<HighlightedCode>>;
+
+/// Loads the grammars and theme, so that the first code block shown
+/// doesn't wait for them.
+pub fn preload() {
+    LazyLock::force(&SYNTAXES);
+    LazyLock::force(&THEME);
+}
+
+/// Highlights the lines of a code block written in `language`, which can be
+/// a name or file extension such as `rust`, `sh` or `yml`. Returns `None`
+/// for languages without a bundled grammar and for blocks over the size limits.
+pub fn highlight(lines: &[String], language: &str) -> Option<Arc<HighlightedCode>> {
+    if lines.len() > MAX_LINES || lines.iter().map(String::len).sum::<usize>() > MAX_BYTES {
+        return None;
+    }
+    let key = (language.to_string(), lines.join("\n"));
+    if let Some(highlighted) = CACHE.lock().unwrap().get(&key) {
+        return Some(Arc::clone(highlighted));
+    }
+    let syntax = SYNTAXES.find_syntax_by_token(language)?;
+    let mut highlighter = HighlightLines::new(syntax, &THEME);
+    let mut highlighted = Vec::with_capacity(lines.len());
+    for line in lines {
+        let ranges = highlighter.highlight_line(line, &SYNTAXES).ok()?;
+        highlighted.push(
+            ranges
+                .into_iter()
+                .map(|(style, text)| Span::styled(text.to_string(), span_style(style)))
+                .collect(),
+        );
+    }
+    let mut cache = CACHE.lock().unwrap();
+    if cache.len() >= CACHE_SIZE {
+        cache.clear();
+    }
+    let highlighted = Arc::new(highlighted);
+    cache.insert(key, Arc::clone(&highlighted));
+    Some(highlighted)
+}
+
+/// The foreground and font of a syntect style. The background is left to
+/// the message pane.
+fn span_style(style: highlighting::Style) -> Style {
+    let mut span_style = Style::default();
+    if let Some(color) = terminal_color(style.foreground) {
+        span_style = span_style.fg(color);
+    }
+    for (font_style, modifier) in [
+        (FontStyle::BOLD, Modifier::BOLD),
+        (FontStyle::ITALIC, Modifier::ITALIC),
+        (FontStyle::UNDERLINE, Modifier::UNDERLINED),
+    ] {
+        if style.font_style.contains(font_style) {
+            span_style = span_style.add_modifier(modifier);
+        }
+    }
+    span_style
+}
+
+/// The `ansi` theme keeps a palette index in the red channel, marked by an
+/// alpha of 0. An alpha of 1 means the terminal's default colour.
+fn terminal_color(color: highlighting::Color) -> Option<Color> {
+    match color.a {
+        0 => Some(Color::Indexed(color.r)),
+        1 => None,
+        _ => Some(Color::Rgb(color.r, color.g, color.b)),
+    }
+}
@@ -0,0 +1,120 @@
+/// Inputs the user sent, oldest first, browsed with Up/Down and searched
+/// with Ctrl-R.
+#[derive(Default)]
+pub struct InputHistory {
+    entries: Vec<String>,
+    /// Entry shown in the composer while browsing.
+    position: Option<usize>,
+    /// Text the user was typing before browsing or searching.
+    draft: String,
+    search: Option<HistorySearch>,
+}
+
+/// A Ctrl-R search in progress.
+pub struct HistorySearch {
+    pub query: String,
+    /// Entry matching the query, if any.
+    pub found: Option<usize>,
+}
+
+impl InputHistory {
+    pub fn new(entries: Vec<String>) -> Self {
+        Self {
+            entries,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a sent input, unless it repeats the last one. Returns whether it was added.
+    pub fn push(&mut self, input: &str) -> bool {
+        self.position = None;
+        self.search = None;
+        if self.entries.last().is_some_and(|last| last == input) {
+            return false;
+        }
+        self.entries.push(input.to_string());
+        true
+    }
+
+    /// Steps to the previous (older) entry. `current` is kept as the draft
+    /// when browsing starts.
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(position) => position.saturating_sub(1),
+        };
+        self.position = Some(position);
+        Some(&self.entries[position])
+    }
+
+    /// Steps to the next (newer) entry, and back to the draft after the newest.
+    pub fn newer(&mut self) -> Option<String> {
+        let position = self.position? + 1;
+        if position < self.entries.len() {
+            self.position = Some(position);
+            Some(self.entries[position].clone())
+        } else {
+            self.position = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+
+    pub fn search(&self) -> Option<&HistorySearch> {
+        self.search.as_ref()
+    }
+
+    pub fn start_search(&mut self, current: &str) {
+        if self.search.is_none() {
+            self.draft = current.to_string();
+            self.search = Some(HistorySearch {
+                query: String::new(),
+                found: None,
+            });
+        }
+    }
+
+    /// Changes the query and returns the newest match at or before the
+    /// current one.
+    pub fn set_search_query(&mut self, query: String) -> Option<&str> {
+        let search = self.search.as_mut()?;
+        let end = search.found.map_or(self.entries.len(), |found| found + 1);
+        search.query = query;
+        search.found = find_before(&self.entries, &search.query, end);
+        search.found.map(|found| self.entries[found].as_str())
+    }
+
+    /// Finds an older match for the current query.
+    pub fn search_older(&mut self) -> Option<&str> {
+        let search = self.search.as_mut()?;
+        let end = search.found.unwrap_or(self.entries.len());
+        if let Some(found) = find_before(&self.entries, &search.query, end) {
+            search.found = Some(found);
+        }
+        search.found.map(|found| self.entries[found].as_str())
+    }
+
+    /// Ends the search, keeping the match in the composer for browsing from there.
+    pub fn accept_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            self.position = search.found;
+        }
+    }
+
+    /// Ends the search and returns the draft it started from.
+    pub fn cancel_search(&mut self) -> String {
+        self.search = None;
+        std::mem::take(&mut self.draft)
+    }
+}
+
+/// Index of the newest entry before `end` containing `query`, ignoring case.
+fn find_before(entries: &[String], query: &str, end: usize) -> Option<usize> {
+    let query = query.to_lowercase();
+    entries[..end]
+        .iter()
+        .rposition(|entry| entry.to_lowercase().contains(&query))
+}
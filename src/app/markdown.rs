@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+/// Part of a message as shown, after parsing the Markdown subset messages
+/// support: `*bold*`, `_italic_`, `` `code` ``, ``` fenced blocks and `>` quotes.
+#[derive(Debug, PartialEq)]
+pub enum Block<'a> {
+    /// A line of text.
+    Text(Vec<Fragment<'a>>),
+    /// A line quoted with `>`.
+    Quote(Vec<Fragment<'a>>),
+    /// The lines of a fenced code block, shown as typed. The language is
+    /// whatever follows the opening fence, e.g. `rust`.
+    Code {
+        language: Option<&'a str>,
+        lines: Vec<&'a str>,
+    },
+}
+
+/// Text within a line sharing the same formatting.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fragment<'a> {
+    pub text: &'a str,
+    pub bold: bool,
+    pub italic: bool,
+    pub code: bool,
+}
+
+const FENCE: &str = "```";
+
+/// Splits message content into lines, quotes and code blocks. A code block
+/// that is never closed runs to the end of the message.
+pub fn parse(content: &str) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    let mut lines = content.split('\n');
+    while let Some(line) = lines.next() {
+        if let Some(language) = opening_fence(line) {
+            let code = lines
+                .by_ref()
+                .take_while(|line| !line.trim_start().starts_with(FENCE))
+                .collect();
+            blocks.push(Block::Code {
+                language,
+                lines: code,
+            });
+        } else if let Some(quoted) = line.strip_prefix('>') {
+            let quoted = quoted.strip_prefix(' ').unwrap_or(quoted);
+            blocks.push(Block::Quote(parse_inline(quoted)));
+        } else {
+            blocks.push(Block::Text(parse_inline(line)));
+        }
+    }
+    blocks
+}
+
+/// The language of a line opening a code block, if it is one. A line that
+/// also closes its fence, e.g. ```` ```ls -la``` ````, is inline code instead.
+fn opening_fence(line: &str) -> Option<Option<&str>> {
+    let info = line.trim_start().strip_prefix(FENCE)?;
+    if info.contains(FENCE) {
+        return None;
+    }
+    let language = info.trim();
+    Some((!language.is_empty()).then_some(language))
+}
+
+/// Splits a line into fragments. Emphasis only starts and ends next to a
+/// word, so `snake_case` and `2*3*4` stay as typed.
+pub fn parse_inline(text: &str) -> Vec<Fragment<'_>> {
+    let mut fragments = Vec::new();
+    inline(text, false, false, &mut fragments);
+    fragments
+}
+
+fn inline<'a>(text: &'a str, bold: bool, italic: bool, fragments: &mut Vec<Fragment<'a>>) {
+    // Where a search for each closing delimiter, by character and length,
+    // first found nothing. Later searches would only see a part of the same
+    // text, so they are skipped to keep parsing linear.
+    let mut unclosed: HashMap<(char, usize), usize> = HashMap::new();
+    let push = |fragments: &mut Vec<Fragment<'a>>, text: &'a str, code: bool| {
+        if !text.is_empty() {
+            fragments.push(Fragment {
+                text,
+                bold,
+                italic,
+                code,
+            });
+        }
+    };
+    let mut start = 0;
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        let rest = &text[index..];
+        if c == '`' {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let closing = find_closing(&mut unclosed, ('`', ticks), index + ticks, || {
+                closing_ticks(&rest[ticks..], ticks)
+            });
+            if let Some(end) = closing {
+                push(fragments, &text[start..index], false);
+                push(fragments, &rest[ticks..ticks + end], true);
+                index += ticks + end + ticks;
+                start = index;
+            } else {
+                index += ticks;
+            }
+            continue;
+        }
+        if (c == '*' || c == '_')
+            && let Some(delimiter) = opening(text, index, c)
+            && let Some(end) = find_closing(
+                &mut unclosed,
+                (c, delimiter.len()),
+                index + delimiter.len(),
+                || closing(text, index + delimiter.len(), delimiter, c),
+            )
+        {
+            push(fragments, &text[start..index], false);
+            inline(
+                &text[index + delimiter.len()..end],
+                bold || c == '*',
+                italic || c == '_',
+                fragments,
+            );
+            index = end + delimiter.len();
+            start = index;
+            continue;
+        }
+        index += c.len_utf8();
+    }
+    push(fragments, &text[start..], false);
+}
+
+/// Offset of the next run of exactly `count` backticks in `text`.
+fn closing_ticks(text: &str, count: usize) -> Option<usize> {
+    let mut index = 0;
+    while let Some(offset) = text[index..].find('`') {
+        let run_start = index + offset;
+        let run = text[run_start..].len() - text[run_start..].trim_start_matches('`').len();
+        if run == count {
+            return Some(run_start);
+        }
+        index = run_start + run;
+    }
+    None
+}
+
+/// Runs `search` for a closing delimiter from `start`, unless one from an
+/// earlier position already failed.
+fn find_closing(
+    unclosed: &mut HashMap<(char, usize), usize>,
+    delimiter: (char, usize),
+    start: usize,
+    search: impl FnOnce() -> Option<usize>,
+) -> Option<usize> {
+    if unclosed
+        .get(&delimiter)
+        .is_some_and(|&failed| failed <= start)
+    {
+        return None;
+    }
+    let end = search();
+    if end.is_none() {
+        unclosed.insert(delimiter, start);
+    }
+    end
+}
+
+/// The delimiter starting at `start`, one or two of `c`, if it can open
+/// emphasis there.
+fn opening(text: &str, start: usize, c: char) -> Option<&str> {
+    let single = c.len_utf8();
+    let delimiter = if text[start + single..].starts_with(c) {
+        &text[start..start + 2 * single]
+    } else {
+        &text[start..start + single]
+    };
+    let before = text[..start].chars().next_back();
+    let after = text[start + delimiter.len()..].chars().next();
+    if before.is_some_and(char::is_alphanumeric) || after.is_none_or(char::is_whitespace) {
+        return None;
+    }
+    Some(delimiter)
+}
+
+/// Where the delimiter closing emphasis whose text starts at `inner_start`
+/// starts.
+fn closing(text: &str, inner_start: usize, delimiter: &str, c: char) -> Option<usize> {
+    let single = c.len_utf8();
+    let mut index = inner_start;
+    while let Some(offset) = text[index..].find(delimiter) {
+        let end = index + offset;
+        let before = text[..end].chars().next_back();
+        let after = text[end + delimiter.len()..].chars().next();
+        if end > inner_start
+            && before.is_some_and(|before| !before.is_whitespace() && before != c)
+            && !after.is_some_and(|after| after.is_alphanumeric() || after == c)
+        {
+            return Some(end);
+        }
+        index = end + single;
+    }
+    None
+}
@@ -0,0 +1,52 @@
+use crate::app::{
+    AppController,
+    command_registry::{Command, CommandArgs, CommandFuture, CommandRegistry},
+};
+
+/// Adds the commands for the mention inbox and bell.
+pub fn register(registry: &mut CommandRegistry) {
+    let commands = [
+        Command {
+            name: "/mentions",
+            usage: "",
+            description: "List messages that mention you",
+            handler: mentions,
+        },
+        Command {
+            name: "/bell",
+            usage: "[on | off]",
+            description: "Show or set whether mentions ring the terminal bell",
+            handler: bell,
+        },
+    ];
+    for command in commands {
+        registry.register(command);
+    }
+}
+
+fn mentions<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.show_mentions().await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn bell<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => {}
+            ["on"] => controller.app_state.mention_bell = true,
+            ["off"] => controller.app_state.mention_bell = false,
+            _ => return Err(args.usage_error()),
+        }
+        let state = if controller.app_state.mention_bell {
+            "on"
+        } else {
+            "off"
+        };
+        controller.notify(format!("The mention bell is {}", state));
+        Ok(())
+    })
+}
@@ -1,8 +1,20 @@
+#[allow(clippy::module_inception)]
 pub mod app;
+pub mod app_commands;
 pub mod app_controller;
 pub mod app_input_parse;
 pub mod app_state;
+pub mod command_registry;
+pub mod completion;
+pub mod composer;
+pub mod highlight;
+pub mod input_history;
+pub mod markdown;
+pub mod mentions;
+pub mod presence;
+pub mod reactions;
 pub mod terminal;
+pub mod wrap;
 
 pub use app::App;
 pub use app_controller::AppController;
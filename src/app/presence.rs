@@ -0,0 +1,116 @@
+use crate::{
+    app::{
+        AppController,
+        command_registry::{Command, CommandArgs, CommandFuture, CommandRegistry},
+    },
+    db_models::Presence,
+};
+
+/// Adds the commands for setting away and a custom status.
+pub fn register(registry: &mut CommandRegistry) {
+    let commands = [
+        Command {
+            name: "/away",
+            usage: "[status]",
+            description: "Show yourself as away, optionally with a status",
+            handler: away,
+        },
+        Command {
+            name: "/back",
+            usage: "",
+            description: "Show yourself as online again",
+            handler: back,
+        },
+        Command {
+            name: "/status",
+            usage: "[text | -]",
+            description: "Show your status, set it, or clear it with -",
+            handler: status,
+        },
+    ];
+    for command in commands {
+        registry.register(command);
+    }
+}
+
+fn away<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.text {
+            "" => controller.set_away(true, None).await,
+            text => controller.set_away(true, Some(text)).await,
+        }
+    })
+}
+
+fn back<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.words.as_slice() {
+            [] => controller.set_away(false, None).await,
+            _ => Err(args.usage_error()),
+        }
+    })
+}
+
+fn status<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        match args.text {
+            "" => controller.show_status(),
+            "-" => controller.set_status_text(None).await,
+            text => controller.set_status_text(Some(text)).await,
+        }
+    })
+}
+
+impl AppController {
+    async fn set_away(&mut self, away: bool, text: Option<&str>) -> Result<(), anyhow::Error> {
+        if text.is_some() {
+            self.server_controller
+                .set_status_text(&self.app_state.user, text)
+                .await?;
+        }
+        self.server_controller
+            .set_away(&self.app_state.user, away)
+            .await;
+        self.notify(if away {
+            "You are marked as away".to_string()
+        } else {
+            "You are back online".to_string()
+        });
+        Ok(())
+    }
+
+    fn show_status(&mut self) -> Result<(), anyhow::Error> {
+        let status = self
+            .app_state
+            .statuses
+            .get(&self.app_state.user.username)
+            .cloned()
+            .unwrap_or_default();
+        let mut notice = format!("You are {}", presence_name(status.presence));
+        if let Some(text) = status.text {
+            notice.push_str(&format!(": {}", text));
+        }
+        self.notify(notice);
+        Ok(())
+    }
+
+    async fn set_status_text(&mut self, text: Option<&str>) -> Result<(), anyhow::Error> {
+        self.server_controller
+            .set_status_text(&self.app_state.user, text)
+            .await?;
+        match text {
+            Some(text) => self.notify(format!("Your status is now: {}", text)),
+            None => self.notify("Cleared your status".to_string()),
+        }
+        Ok(())
+    }
+}
+
+/// How a presence is written in notices, e.g. `away`.
+pub fn presence_name(presence: Presence) -> &'static str {
+    match presence {
+        Presence::Online => "online",
+        Presence::Idle => "idle",
+        Presence::Away => "away",
+    }
+}
@@ -0,0 +1,25 @@
+use crate::app::{
+    AppController,
+    command_registry::{Command, CommandArgs, CommandFuture, CommandRegistry},
+    completion::parse_emoji,
+};
+
+/// Adds the command for reacting to messages.
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Command {
+        name: "/react",
+        usage: "<emoji>",
+        description: "Toggle a reaction to the selected or last message, e.g. /react :tada:",
+        handler: react,
+    });
+}
+
+fn react<'a>(controller: &'a mut AppController, args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async move {
+        let [emoji] = args.words.as_slice() else {
+            return Err(args.usage_error());
+        };
+        let emoji = parse_emoji(emoji).ok_or_else(|| anyhow::anyhow!("Unknown emoji {}", emoji))?;
+        controller.react(emoji).await
+    })
+}
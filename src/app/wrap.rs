@@ -0,0 +1,164 @@
+use ratatui::{
+    style::Style,
+    text::{Line, Span},
+};
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Breaks a line into rows of at most `width` terminal cells, between words
+/// where possible. Widths are measured per grapheme, so emoji and CJK take
+/// two cells. Rows after the first start with `indent` spaces and drop the
+/// whitespace they were broken at. Each row keeps the line's style and alignment.
+pub fn wrap_line<'a>(line: Line<'a>, width: usize, indent: usize) -> Vec<Line<'a>> {
+    let width = width.max(1);
+    let indent = if indent < width { indent } else { 0 };
+    let mut rows: Vec<Vec<(&str, Style)>> = vec![Vec::new()];
+    let mut row_width = 0;
+
+    for word in words(&line) {
+        let word_width: usize = word.iter().map(|(grapheme, _)| grapheme.width()).sum();
+        let row_start = if rows.len() > 1 { indent } else { 0 };
+        let is_space = word
+            .first()
+            .is_some_and(|(grapheme, _)| grapheme.chars().all(char::is_whitespace));
+        if is_space {
+            // Whitespace that doesn't fit, or that a row was broken at, is dropped.
+            if row_width + word_width <= width && (rows.len() == 1 || row_width > row_start) {
+                rows.last_mut().unwrap().extend(word);
+                row_width += word_width;
+            }
+            continue;
+        }
+        if row_width + word_width > width && row_width > row_start {
+            break_row(&mut rows);
+            row_width = indent;
+        }
+        if row_width + word_width <= width {
+            rows.last_mut().unwrap().extend(word);
+            row_width += word_width;
+            continue;
+        }
+        // The word is longer than a row, so it is broken between graphemes.
+        for (grapheme, style) in word {
+            let grapheme_width = grapheme.width();
+            let row_start = if rows.len() > 1 { indent } else { 0 };
+            if row_width + grapheme_width > width && row_width > row_start {
+                break_row(&mut rows);
+                row_width = indent;
+            }
+            rows.last_mut().unwrap().push((grapheme, style));
+            row_width += grapheme_width;
+        }
+    }
+
+    into_lines(rows, &line, indent)
+}
+
+/// Breaks a line into rows of at most `width` terminal cells between any two
+/// graphemes, keeping all whitespace, for text such as code whose layout
+/// matters. Rows after the first start with `indent` spaces.
+pub fn break_line<'a>(line: Line<'a>, width: usize, indent: usize) -> Vec<Line<'a>> {
+    let width = width.max(1);
+    let indent = if indent < width { indent } else { 0 };
+    let mut rows: Vec<Vec<(&str, Style)>> = vec![Vec::new()];
+    let mut row_width = 0;
+    for (grapheme, style) in words(&line).into_iter().flatten() {
+        let grapheme_width = grapheme.width();
+        let row_start = if rows.len() > 1 { indent } else { 0 };
+        if row_width + grapheme_width > width && row_width > row_start {
+            rows.push(Vec::new());
+            row_width = indent;
+        }
+        rows.last_mut().unwrap().push((grapheme, style));
+        row_width += grapheme_width;
+    }
+    into_lines(rows, &line, indent)
+}
+
+/// Joins the graphemes of each row back into spans, indenting all rows but
+/// the first. Each row keeps the line's style and alignment.
+fn into_lines<'a>(rows: Vec<Vec<(&str, Style)>>, line: &Line, indent: usize) -> Vec<Line<'a>> {
+    rows.into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let mut spans: Vec<Span<'a>> = Vec::new();
+            if index > 0 && indent > 0 {
+                spans.push(Span::raw(" ".repeat(indent)));
+            }
+            for (text, style) in row {
+                match spans.last_mut() {
+                    Some(last) if last.style == style => last.content.to_mut().push_str(text),
+                    _ => spans.push(Span::styled(text.to_string(), style)),
+                }
+            }
+            let mut row = Line::from(spans).style(line.style);
+            row.alignment = line.alignment;
+            row
+        })
+        .collect()
+}
+
+/// Starts a new row, dropping whitespace at the end of the current one.
+fn break_row(rows: &mut Vec<Vec<(&str, Style)>>) {
+    if let Some(row) = rows.last_mut() {
+        while row
+            .last()
+            .is_some_and(|(grapheme, _)| grapheme.chars().all(char::is_whitespace))
+        {
+            row.pop();
+        }
+    }
+    rows.push(Vec::new());
+}
+
+/// Splits a line into words and single whitespace graphemes, each as
+/// graphemes with the style of the span they came from. A word can span
+/// several spans.
+fn words<'b>(line: &'b Line) -> Vec<Vec<(&'b str, Style)>> {
+    let mut words: Vec<Vec<(&str, Style)>> = Vec::new();
+    let mut in_word = false;
+    for span in &line.spans {
+        for grapheme in span.content.graphemes(true) {
+            let is_space = grapheme.chars().all(char::is_whitespace);
+            if is_space || !in_word {
+                words.push(Vec::new());
+            }
+            words.last_mut().unwrap().push((grapheme, span.style));
+            in_word = !is_space;
+        }
+    }
+    words
+}
+
+/// Splits the input into rows of at most `width` cells, breaking at newlines.
+/// Also returns the row and column of the cursor, given as a byte offset. The
+/// column counts chars.
+pub fn wrap_input(text: &str, cursor: usize, width: usize) -> (Vec<String>, (usize, usize)) {
+    let mut rows = vec![String::new()];
+    let mut row_width = 0;
+    let mut position = (0, 0);
+    for (index, c) in text.char_indices().chain([(text.len(), '\n')]) {
+        // The cursor takes a cell where the newline is.
+        let char_width = if c == '\n' { 1 } else { c.width().unwrap_or(0) };
+        // A full row wraps before its next char, or before the cursor at its end.
+        if row_width + char_width > width && row_width > 0 && (c != '\n' || index == cursor) {
+            rows.push(String::new());
+            row_width = 0;
+        }
+        if index == cursor {
+            position = (rows.len() - 1, rows.last().unwrap().chars().count());
+        }
+        if index == text.len() {
+            break;
+        }
+        if c == '\n' {
+            rows.push(String::new());
+            row_width = 0;
+        } else {
+            rows.last_mut().unwrap().push(c);
+            row_width += char_width;
+        }
+    }
+    (rows, position)
+}
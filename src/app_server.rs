@@ -1,216 +1,1022 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Instant;
 
 use log::{error, info};
 
-use std::{fs::OpenOptions, net::IpAddr, path::Path};
-
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, broadcast};
+use tokio::task::JoinSet;
 
 use crate::{
-    app::{App, AppController},
-    db_models::{Message, User},
+    app::{App, AppController, command_registry::CommandRegistry, highlight},
+    config::{RegistrationPolicy, ServerConfig},
+    db_models::{
+        Channel, Invite, Mention, Message, MessageSearch, Presence, Reaction, ReadMarker, User,
+        UserKey, UserStatus,
+    },
+    server_events::{EVENT_CHANNEL_CAPACITY, ServerEvent},
+    storage::{SqliteStorage, Storage},
 };
 
 use russh::{
-    keys::PrivateKey,
+    keys::{HashAlg, PrivateKey, PublicKey},
     server::{Config, Server},
 };
 
-use sqlx::Row;
-use sqlx::sqlite::SqlitePool;
+use chrono::{Duration, Utc};
 
 use argon2::{
     Argon2,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
 
-const DB_FILE: &str = "sshlack.db";
+pub const DEFAULT_CHANNEL: &str = "general";
+/// Most results a search returns.
+pub const SEARCH_LIMIT: usize = 100;
+/// Messages loaded before a search hit when jumping to it.
+pub const SEARCH_CONTEXT: usize = 20;
+/// Longest topic a channel can have, in characters.
+pub const MAX_TOPIC_LENGTH: usize = 200;
+/// Inputs kept per user for Up/Down and Ctrl-R in the composer.
+pub const INPUT_HISTORY_LIMIT: usize = 500;
+/// Most mentions listed in the mentions pane.
+pub const MENTIONS_LIMIT: usize = 100;
+/// Longest custom status, in characters.
+pub const MAX_STATUS_LENGTH: usize = 100;
+/// How often idle users and stale typing indicators are checked for.
+const HOUSEKEEPING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// How long someone is shown as typing after their last keystroke.
+const TYPING_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Status of a connected user, and when they last typed anything.
+struct Activity {
+    status: UserStatus,
+    last_input: Instant,
+}
+
+/// Channel a user is typing in, and when they last changed their input.
+struct Typing {
+    channel_id: i64,
+    last_input: Instant,
+}
 
 pub struct AppServerController {
     clients: Mutex<HashMap<usize, Arc<Mutex<AppController>>>>,
     pub next_client_id: Mutex<usize>,
 
     pub users: Mutex<Vec<User>>,
+    /// Presence and status of connected users, by username.
+    activity: Mutex<HashMap<String, Activity>>,
+    /// Users who are typing, by username.
+    typing: Mutex<HashMap<String, Typing>>,
+
+    storage: Box<dyn Storage>,
+
+    events: broadcast::Sender<ServerEvent>,
+
+    /// Slash commands available to clients.
+    pub commands: CommandRegistry,
 
-    db_pool: SqlitePool,
+    pub config: ServerConfig,
 }
 
 impl AppServerController {
-    pub async fn new() -> Result<Self, anyhow::Error> {
-        Self::ensure_db_exists()?;
-
-        match SqlitePool::connect(format!("sqlite://{}", DB_FILE).as_str()).await {
-            Ok(db_pool) => {
-                let controller = Self {
-                    clients: Mutex::new(HashMap::new()),
-                    next_client_id: Mutex::new(0),
-                    users: Mutex::new(Vec::new()),
-                    db_pool: db_pool,
-                };
-                controller.initialise().await?;
-                Ok(controller)
-            }
+    pub async fn new(
+        config: ServerConfig,
+        commands: CommandRegistry,
+    ) -> Result<Self, anyhow::Error> {
+        match Self::open_storage(&config).await {
+            Ok(storage) => Self::with_storage(config, storage, commands).await,
             Err(e) => {
                 error!("Failed to connect to the database: {}", e);
-                Err(e.into())
+                Err(e)
             }
         }
     }
 
-    fn ensure_db_exists() -> Result<(), anyhow::Error> {
-        let path = Path::new(DB_FILE);
+    /// Creates a controller on top of an already opened storage backend,
+    /// offering clients the slash commands in `commands`.
+    pub async fn with_storage(
+        config: ServerConfig,
+        storage: Box<dyn Storage>,
+        commands: CommandRegistry,
+    ) -> Result<Self, anyhow::Error> {
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let controller = Self {
+            clients: Mutex::new(HashMap::new()),
+            next_client_id: Mutex::new(0),
+            users: Mutex::new(Vec::new()),
+            activity: Mutex::new(HashMap::new()),
+            typing: Mutex::new(HashMap::new()),
+            storage,
+            events,
+            commands,
+            config,
+        };
+        controller.initialise().await?;
+        Ok(controller)
+    }
 
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)?;
-        Ok(())
+    async fn open_storage(config: &ServerConfig) -> Result<Box<dyn Storage>, anyhow::Error> {
+        match &config.postgres_url {
+            #[cfg(feature = "postgres")]
+            Some(url) => Ok(Box::new(
+                crate::storage::PostgresStorage::connect(url).await?,
+            )),
+            #[cfg(not(feature = "postgres"))]
+            Some(_) => Err(anyhow::anyhow!(
+                "postgres_url is set, but sshlack was built without the postgres feature"
+            )),
+            None => Ok(Box::new(SqliteStorage::connect(&config.db_path).await?)),
+        }
     }
 
-    pub async fn add_client(&self, app_controller: Arc<Mutex<AppController>>) {
-        // let app_controller = Arc::clone(&app_controller);
-        let mut users = self.users.lock().await;
-        users.push(app_controller.lock().await.app_state.user.clone());
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
 
-        let mut next_client_id = self.next_client_id.lock().await;
-        let mut clients = self.clients.lock().await;
-        clients.insert(*next_client_id, Arc::clone(&app_controller));
+    fn publish(&self, event: ServerEvent) {
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.events.send(event);
+    }
+
+    pub async fn add_client(&self, app_controller: Arc<Mutex<AppController>>) -> usize {
+        let user = app_controller.lock().await.app_state.user.clone();
+        let text = match self.storage.get_status_text(&user.username).await {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to load the status of {}: {}", user.username, e);
+                None
+            }
+        };
+        self.activity
+            .lock()
+            .await
+            .entry(user.username.clone())
+            .or_insert_with(|| Activity {
+                status: UserStatus {
+                    presence: Presence::Online,
+                    text,
+                },
+                last_input: Instant::now(),
+            });
+        self.users.lock().await.push(user.clone());
 
+        let mut next_client_id = self.next_client_id.lock().await;
+        let client_id = *next_client_id;
+        self.clients
+            .lock()
+            .await
+            .insert(client_id, Arc::clone(&app_controller));
         *next_client_id += 1;
+
+        let mut events = self.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = events.recv().await;
+                let mut app_controller = app_controller.lock().await;
+                if !app_controller.active {
+                    break;
+                }
+                match event {
+                    Ok(event) => app_controller.handle_event(event).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        info!(
+                            "{} lagged behind by {} events, reloading",
+                            app_controller.app_state.user.username, skipped
+                        );
+                        if let Err(e) = app_controller.reload().await {
+                            error!("Failed to reload client state: {}", e);
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                if let Err(e) = app_controller.draw() {
+                    error!("Failed to draw client: {}", e);
+                }
+            }
+        });
+
+        self.publish(ServerEvent::UserJoined(user));
+        client_id
+    }
+
+    pub async fn remove_client(&self, client_id: usize) {
+        let Some(app_controller) = self.clients.lock().await.remove(&client_id) else {
+            return;
+        };
+        let user = app_controller.lock().await.app_state.user.clone();
+        info!("{} disconnected", user.username);
+
+        let mut users = self.users.lock().await;
+        if let Some(index) = users.iter().position(|u| u.username == user.username) {
+            users.remove(index);
+        }
+        // The user may still be connected from another session.
+        if !users.iter().any(|u| u.username == user.username) {
+            self.activity.lock().await.remove(&user.username);
+        }
+        drop(users);
+
+        self.set_typing(&user, 0, false).await;
+        self.publish(ServerEvent::UserLeft(user));
     }
 
     pub async fn initialise(&self) -> Result<(), anyhow::Error> {
-        sqlx::query("CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY AUTOINCREMENT, content TEXT, sender TEXT)").execute(&self.db_pool).await?;
-        sqlx::query("CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY AUTOINCREMENT, username TEXT UNIQUE, password_hash TEXT)").execute(&self.db_pool).await?;
+        self.storage.migrate().await?;
+        if self.storage.get_channel(DEFAULT_CHANNEL).await?.is_none() {
+            self.storage
+                .create_channel(DEFAULT_CHANNEL, &User::bot().username, false, &[])
+                .await?;
+        }
         Ok(())
     }
 
-    pub async fn send_message(&self, message: Message) -> Result<(), anyhow::Error> {
-        let mut conn = self.db_pool.acquire().await?;
-        sqlx::query("INSERT INTO messages (content, sender) VALUES (?, ?)")
-            .bind(message.content)
-            .bind(message.sender.username)
-            .execute(&mut *conn)
+    pub async fn send_message(&self, mut message: Message) -> Result<(), anyhow::Error> {
+        if let Some(parent_id) = message.parent_id {
+            let parent = self.get_message(parent_id).await?;
+            if parent.channel_id != message.channel_id || parent.deleted {
+                return Err(anyhow::anyhow!("That message can't be replied to"));
+            }
+            // Threads are one level deep, so replying to a reply joins its thread.
+            message.parent_id = Some(parent.parent_id.unwrap_or(parent.id));
+        }
+        message.id = self.storage.insert_message(&message).await?;
+        self.record_mentions(&message).await?;
+        self.set_typing(&message.sender, message.channel_id, false)
+            .await;
+        let parent_id = message.parent_id;
+        self.publish(ServerEvent::NewMessage(message));
+        if let Some(parent_id) = parent_id {
+            self.publish_reply_count(parent_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Sends out a thread's parent message so clients update its reply count.
+    async fn publish_reply_count(&self, parent_id: i64) -> Result<(), anyhow::Error> {
+        self.publish(ServerEvent::MessageUpdated(
+            self.get_message(parent_id).await?,
+        ));
+        Ok(())
+    }
+
+    /// The replies to a message in one of `user`'s channels, oldest first.
+    pub async fn get_replies(
+        &self,
+        user: &User,
+        parent_id: i64,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let parent = self.get_message(parent_id).await?;
+        let members = self.storage.get_channel_members(parent.channel_id).await?;
+        if !members.contains(&user.username) {
+            return Err(anyhow::anyhow!("You are not a member of this channel"));
+        }
+        self.storage.get_replies(parent_id).await
+    }
+
+    /// Stores a mention for each member of the message's channel it names by
+    /// username or nickname. Mentions of users who are online and active are
+    /// stored as seen, since they are notified right away.
+    async fn record_mentions(&self, message: &Message) -> Result<(), anyhow::Error> {
+        let mentions = message.mentions();
+        if mentions.is_empty() {
+            return Ok(());
+        }
+        let members = self.storage.get_channel_members(message.channel_id).await?;
+        let nicknames = self.get_nicknames().await?;
+        let statuses = self.get_statuses().await;
+        for member in members {
+            if member == message.sender.username {
+                continue;
+            }
+            let nickname = nicknames.get(&member);
+            let mentioned = mentions.iter().any(|mention| {
+                mention.eq_ignore_ascii_case(&member)
+                    || nickname.is_some_and(|nickname| nickname.eq_ignore_ascii_case(mention))
+            });
+            if mentioned {
+                let seen = statuses
+                    .get(&member)
+                    .is_some_and(|status| status.presence == Presence::Online);
+                self.storage.add_mention(message.id, &member, seen).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recent messages mentioning `user`, newest first.
+    pub async fn get_mentions(&self, user: &User) -> Result<Vec<Mention>, anyhow::Error> {
+        self.storage
+            .get_mentions(&user.username, MENTIONS_LIMIT)
+            .await
+    }
+
+    pub async fn mark_mentions_seen(&self, user: &User) -> Result<(), anyhow::Error> {
+        self.storage.mark_mentions_seen(&user.username).await
+    }
+
+    /// Adds `user`'s reaction to a message in one of their channels, or removes
+    /// it if they already reacted with `emoji`. Returns whether it was added.
+    pub async fn toggle_reaction(
+        &self,
+        user: &User,
+        message_id: i64,
+        emoji: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let message = self.get_message(message_id).await?;
+        if message.deleted {
+            return Err(anyhow::anyhow!("That message was deleted"));
+        }
+        let members = self.storage.get_channel_members(message.channel_id).await?;
+        if !members.contains(&user.username) {
+            return Err(anyhow::anyhow!("You are not a member of this channel"));
+        }
+        let added = self
+            .storage
+            .toggle_reaction(message_id, &user.username, emoji)
             .await?;
+        self.publish(ServerEvent::ReactionsChanged {
+            message_id,
+            reactions: self.storage.get_reactions(&[message_id]).await?,
+        });
+        Ok(added)
+    }
+
+    pub async fn get_reactions(&self, message_ids: &[i64]) -> Result<Vec<Reaction>, anyhow::Error> {
+        self.storage.get_reactions(message_ids).await
+    }
+
+    /// Records that `user` has read a channel up to `message_id`.
+    pub async fn mark_read(
+        &self,
+        user: &User,
+        channel_id: i64,
+        message_id: i64,
+    ) -> Result<(), anyhow::Error> {
+        self.storage
+            .mark_read(channel_id, &user.username, message_id)
+            .await
+    }
+
+    pub async fn get_read_markers(&self, user: &User) -> Result<Vec<ReadMarker>, anyhow::Error> {
+        self.storage.get_read_markers(&user.username).await
+    }
+
+    /// Returns a page of the most recent messages of a channel, or of those
+    /// older than `before_id`. Direct conversations only return messages when
+    /// `user` is one of their members.
+    pub async fn get_messages(
+        &self,
+        channel_id: i64,
+        user: &User,
+        before_id: Option<i64>,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        self.storage
+            .get_messages(
+                channel_id,
+                &user.username,
+                before_id,
+                self.config.history_page(),
+            )
+            .await
+    }
+
+    /// Returns a page of the messages of a channel newer than `after_id`.
+    pub async fn get_messages_after(
+        &self,
+        channel_id: i64,
+        user: &User,
+        after_id: i64,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        self.storage
+            .get_messages_around(
+                channel_id,
+                &user.username,
+                after_id.saturating_add(1),
+                0,
+                self.config.history_page() - 1,
+            )
+            .await
+    }
+
+    /// Returns a channel's history starting a few messages before `message_id`.
+    pub async fn get_messages_around(
+        &self,
+        channel_id: i64,
+        user: &User,
+        message_id: i64,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        self.storage
+            .get_messages_around(
+                channel_id,
+                &user.username,
+                message_id,
+                SEARCH_CONTEXT,
+                self.config.history_page(),
+            )
+            .await
+    }
+
+    /// Searches the channels `user` is a member of, newest first.
+    pub async fn search_messages(
+        &self,
+        user: &User,
+        search: &MessageSearch,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        self.storage
+            .search_messages(search, &user.username, SEARCH_LIMIT)
+            .await
+    }
+
+    pub async fn get_message(&self, message_id: i64) -> Result<Message, anyhow::Error> {
+        self.storage
+            .get_message(message_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Message {} does not exist", message_id))
+    }
+
+    /// Replaces the content of one of `user`'s own messages.
+    pub async fn edit_message(
+        &self,
+        user: &User,
+        message_id: i64,
+        content: String,
+    ) -> Result<(), anyhow::Error> {
+        let updated = self
+            .storage
+            .update_message(message_id, &user.username, &content, Utc::now())
+            .await?;
+        if !updated {
+            return Err(anyhow::anyhow!("You can only edit your own messages"));
+        }
+        let message = self.get_message(message_id).await?;
+        self.record_mentions(&message).await?;
+        self.publish(ServerEvent::MessageUpdated(message));
+        Ok(())
+    }
+
+    /// Marks one of `user`'s own messages as deleted and drops its content.
+    pub async fn delete_message(&self, user: &User, message_id: i64) -> Result<(), anyhow::Error> {
+        if !self
+            .storage
+            .delete_message(message_id, &user.username)
+            .await?
+        {
+            return Err(anyhow::anyhow!("You can only delete your own messages"));
+        }
+        let message = self.get_message(message_id).await?;
+        let parent_id = message.parent_id;
+        self.publish(ServerEvent::MessageUpdated(message));
+        if let Some(parent_id) = parent_id {
+            self.publish_reply_count(parent_id).await?;
+        }
         Ok(())
     }
 
-    pub async fn get_messages(&self) -> Result<Vec<Message>, anyhow::Error> {
-        let mut conn = self.db_pool.acquire().await?;
-        let rows = sqlx::query("SELECT * FROM ( SELECT id, content, sender FROM messages ORDER BY id DESC LIMIT 1000) as recent ORDER BY id ASC")
-            .fetch_all(&mut *conn)
+    pub async fn get_channel(&self, name: &str) -> Result<Option<Channel>, anyhow::Error> {
+        self.storage.get_channel(name).await
+    }
+
+    pub async fn create_channel(
+        &self,
+        name: &str,
+        creator: &User,
+    ) -> Result<Channel, anyhow::Error> {
+        if self.get_channel(name).await?.is_some() {
+            return Err(anyhow::anyhow!("#{} already exists", name));
+        }
+        let channel = self
+            .storage
+            .create_channel(name, &creator.username, false, &[])
             .await?;
-        let messages = rows
-            .into_iter()
-            .map(|row| {
-                Message::new(
-                    row.get::<String, _>("content"),
-                    User::authenticated(row.get::<String, _>("sender").as_str()),
-                )
-            })
-            .collect();
-        Ok(messages)
+        info!("{} created #{}", creator.username, name);
+        Ok(channel)
     }
 
-    pub async fn auth_user(&self, username: &str, password: &str) -> Result<User, anyhow::Error> {
-        let mut conn = self.db_pool.acquire().await?;
-        let user_row = sqlx::query("SELECT password_hash FROM users WHERE username =?")
-            .bind(username)
-            .fetch_one(&mut *conn)
-            .await;
+    pub async fn join_channel(&self, user: &User, name: &str) -> Result<Channel, anyhow::Error> {
+        let channel = match self.get_channel(name).await? {
+            Some(channel) if channel.direct => {
+                return Err(anyhow::anyhow!("#{} is a direct conversation", name));
+            }
+            Some(channel) => channel,
+            None => self.create_channel(name, user).await?,
+        };
+        self.storage
+            .add_channel_member(channel.id, &user.username)
+            .await?;
+        Ok(channel)
+    }
+
+    pub async fn leave_channel(&self, user: &User, channel_id: i64) -> Result<(), anyhow::Error> {
+        self.storage
+            .remove_channel_member(channel_id, &user.username)
+            .await
+    }
+
+    pub async fn get_channel_members(&self, channel_id: i64) -> Result<Vec<String>, anyhow::Error> {
+        self.storage.get_channel_members(channel_id).await
+    }
+
+    /// Sets or clears the topic of a channel `user` is a member of.
+    pub async fn set_channel_topic(
+        &self,
+        user: &User,
+        channel_id: i64,
+        topic: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if topic.is_some_and(|topic| topic.chars().count() > MAX_TOPIC_LENGTH) {
+            return Err(anyhow::anyhow!(
+                "Topics can be at most {} characters",
+                MAX_TOPIC_LENGTH
+            ));
+        }
+        let members = self.storage.get_channel_members(channel_id).await?;
+        if !members.contains(&user.username) {
+            return Err(anyhow::anyhow!("You are not a member of this channel"));
+        }
+        self.storage.set_channel_topic(channel_id, topic).await?;
+        self.publish(ServerEvent::TopicChanged {
+            channel_id,
+            topic: topic.map(str::to_string),
+            set_by: user.clone(),
+        });
+        Ok(())
+    }
+
+    pub async fn get_user_channels(&self, user: &User) -> Result<Vec<Channel>, anyhow::Error> {
+        self.storage.get_user_channels(&user.username).await
+    }
+
+    /// Finds or creates the direct conversation between `user` and `recipients`.
+    pub async fn open_direct_channel(
+        &self,
+        user: &User,
+        recipients: &[String],
+    ) -> Result<Channel, anyhow::Error> {
+        for recipient in recipients {
+            if !self.user_exists(recipient).await? {
+                return Err(anyhow::anyhow!("Unknown user @{}", recipient));
+            }
+        }
+
+        let mut members = recipients.to_vec();
+        members.push(user.username.clone());
+        let name = Channel::direct_name(&members);
+        match self.get_channel(&name).await? {
+            Some(channel) if channel.direct => return Ok(channel),
+            Some(_) => return Err(anyhow::anyhow!("{} is not a direct conversation", name)),
+            None => {}
+        }
+
+        let members = Channel::direct_members(&members);
+        let members: Vec<&str> = members.iter().map(String::as_str).collect();
+        let channel = self
+            .storage
+            .create_channel(&name, &user.username, true, &members)
+            .await?;
+
+        self.publish(ServerEvent::DirectChannelOpened(channel.clone()));
+        Ok(channel)
+    }
 
-        let argon2 = Argon2::default();
+    pub async fn user_exists(&self, username: &str) -> Result<bool, anyhow::Error> {
+        self.storage.user_exists(username).await
+    }
+
+    /// Sets or clears the name shown instead of the username. It can't be
+    /// anyone else's username or nickname.
+    pub async fn set_nickname(
+        &self,
+        user: &User,
+        nickname: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(nickname) = nickname {
+            let valid = !nickname.is_empty()
+                && nickname.chars().count() <= 32
+                && nickname
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.');
+            if !valid {
+                return Err(anyhow::anyhow!(
+                    "Nicknames are up to 32 letters, digits, '-', '_' or '.'"
+                ));
+            }
+            let lowercase = nickname.to_lowercase();
+            let mut taken_by_user = false;
+            for candidate in [nickname, lowercase.as_str()] {
+                taken_by_user |=
+                    candidate != user.username && self.storage.user_exists(candidate).await?;
+            }
+            let taken_by_nickname =
+                self.storage
+                    .get_nicknames()
+                    .await?
+                    .into_iter()
+                    .any(|(username, other)| {
+                        username != user.username && other.to_lowercase() == lowercase
+                    });
+            if taken_by_user || taken_by_nickname {
+                return Err(anyhow::anyhow!("{} is already taken", nickname));
+            }
+        }
+        self.storage.set_nickname(&user.username, nickname).await?;
+        self.publish(ServerEvent::NicknameChanged {
+            username: user.username.clone(),
+            nickname: nickname.map(str::to_string),
+        });
+        Ok(())
+    }
+
+    /// Nicknames by username.
+    pub async fn get_nicknames(&self) -> Result<HashMap<String, String>, anyhow::Error> {
+        Ok(self.storage.get_nicknames().await?.into_iter().collect())
+    }
+
+    pub async fn find_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        self.storage.find_usernames(prefix, limit).await
+    }
+
+    pub async fn auth_user(&self, username: &str, password: &str) -> Result<User, anyhow::Error> {
+        let password_hash = self.storage.get_password_hash(username).await?;
 
-        if user_row.is_ok() {
-            let password_hash = user_row.unwrap().get::<String, _>("password_hash");
+        if let Some(password_hash) = password_hash {
             let password_hash = PasswordHash::new(password_hash.as_str())
                 .map_err(|_e| anyhow::anyhow!("Failed to parse password hash"))?;
-            argon2
+            Argon2::default()
                 .verify_password(password.as_bytes(), &password_hash)
                 .map_err(|_e| anyhow::anyhow!("Failed to verify password"))?;
+        } else if self.config.admins.iter().any(|admin| admin == username)
+            && self
+                .storage
+                .create_first_user(username, &hash_password(password)?)
+                .await?
+        {
+            // The first account can be an admin's under any policy, otherwise
+            // nobody could create the first invite.
+            info!("Created the first account, {}", username);
         } else {
-            if !password.is_empty() {
-                let salt = SaltString::generate(&mut OsRng);
-                match argon2.hash_password(password.as_bytes(), &salt) {
-                    Ok(hashed_password) => {
-                        sqlx::query("INSERT INTO users (username, password_hash) VALUES (?,?)")
-                            .bind(username)
-                            .bind(hashed_password.to_string())
-                            .execute(&mut *conn)
-                            .await?;
-                    }
-                    Err(_) => {
-                        return Err(anyhow::anyhow!("Failed to hash password"));
-                    }
+            match self.config.registration {
+                RegistrationPolicy::Open => self.create_account(username, password).await?,
+                RegistrationPolicy::InviteOnly => {
+                    return Err(anyhow::anyhow!("An invite code is required to sign up"));
+                }
+                RegistrationPolicy::Closed => {
+                    return Err(anyhow::anyhow!("Registration is closed"));
                 }
             }
         }
         Ok(User::authenticated(username))
     }
 
+    /// Whether `username` has no account yet and can only sign up with an invite.
+    pub async fn needs_invite(&self, username: &str) -> Result<bool, anyhow::Error> {
+        Ok(self.config.registration == RegistrationPolicy::InviteOnly
+            && !self.user_exists(username).await?)
+    }
+
+    /// Creates the account `username` by redeeming an invite code.
+    pub async fn register_with_invite(
+        &self,
+        username: &str,
+        code: &str,
+        password: &str,
+    ) -> Result<User, anyhow::Error> {
+        if self.config.registration == RegistrationPolicy::Closed {
+            return Err(anyhow::anyhow!("Registration is closed"));
+        }
+        if self.user_exists(username).await? {
+            return Err(anyhow::anyhow!("{} is already taken", username));
+        }
+        let password_hash = hash_password(password)?;
+        if !self
+            .storage
+            .create_user_with_invite(username, &password_hash, code.trim(), Utc::now())
+            .await?
+        {
+            return Err(anyhow::anyhow!("Invalid or expired invite code"));
+        }
+        info!("{} signed up with invite {}", username, code.trim());
+        Ok(User::authenticated(username))
+    }
+
+    async fn create_account(&self, username: &str, password: &str) -> Result<(), anyhow::Error> {
+        self.storage
+            .create_user(username, &hash_password(password)?)
+            .await
+    }
+
+    pub fn is_admin(&self, user: &User) -> bool {
+        self.config.admins.contains(&user.username)
+    }
+
+    pub fn require_admin(&self, user: &User) -> Result<(), anyhow::Error> {
+        if !self.is_admin(user) {
+            return Err(anyhow::anyhow!("Only admins can do that"));
+        }
+        Ok(())
+    }
+
+    /// Lets an admin create an account, which works under every registration policy.
+    pub async fn add_account(
+        &self,
+        admin: &User,
+        username: &str,
+        password: &str,
+    ) -> Result<(), anyhow::Error> {
+        self.require_admin(admin)?;
+        if self.user_exists(username).await? {
+            return Err(anyhow::anyhow!("@{} already exists", username));
+        }
+        self.create_account(username, password).await?;
+        info!("{} created the account {}", admin.username, username);
+        Ok(())
+    }
+
+    /// Creates an invite code. Without `expires_in` the code can be used once,
+    /// otherwise any number of times until it expires.
+    pub async fn create_invite(
+        &self,
+        admin: &User,
+        expires_in: Option<Duration>,
+    ) -> Result<Invite, anyhow::Error> {
+        self.require_admin(admin)?;
+        let expires_at = expires_in
+            .map(|duration| {
+                Utc::now()
+                    .checked_add_signed(duration)
+                    .ok_or_else(|| anyhow::anyhow!("Invites can't be valid that long"))
+            })
+            .transpose()?;
+        let invite = Invite::generate(admin, expires_at);
+        self.storage.create_invite(&invite).await?;
+        info!("{} created invite {}", admin.username, invite.code);
+        Ok(invite)
+    }
+
+    pub async fn get_invites(&self, admin: &User) -> Result<Vec<Invite>, anyhow::Error> {
+        self.require_admin(admin)?;
+        self.storage.get_invites().await
+    }
+
+    pub async fn revoke_invite(&self, admin: &User, code: &str) -> Result<(), anyhow::Error> {
+        self.require_admin(admin)?;
+        if !self.storage.delete_invite(code).await? {
+            return Err(anyhow::anyhow!("No invite {}", code));
+        }
+        info!("{} revoked invite {}", admin.username, code);
+        Ok(())
+    }
+
+    /// Authenticates `username` with a public key whose ownership russh has already verified.
+    pub async fn auth_user_key(
+        &self,
+        username: &str,
+        key: &PublicKey,
+    ) -> Result<User, anyhow::Error> {
+        if !self
+            .storage
+            .user_has_key(username, &UserKey::encode(key)?)
+            .await?
+        {
+            return Err(anyhow::anyhow!("Key is not registered for {}", username));
+        }
+        Ok(User::authenticated(username))
+    }
+
+    pub async fn add_user_key(&self, user: &User, key: &PublicKey) -> Result<(), anyhow::Error> {
+        let public_key = UserKey::encode(key)?;
+        if self.storage.key_exists(&public_key).await? {
+            return Err(anyhow::anyhow!("This key is already registered"));
+        }
+        self.storage
+            .add_user_key(&user.username, &public_key, key.comment())
+            .await?;
+        info!(
+            "{} registered key {}",
+            user.username,
+            key.fingerprint(HashAlg::Sha256)
+        );
+        Ok(())
+    }
+
+    pub async fn get_user_keys(&self, user: &User) -> Result<Vec<UserKey>, anyhow::Error> {
+        self.storage.get_user_keys(&user.username).await
+    }
+
+    pub async fn revoke_user_key(&self, user: &User, key_id: i64) -> Result<(), anyhow::Error> {
+        if !self.storage.delete_user_key(&user.username, key_id).await? {
+            return Err(anyhow::anyhow!("No key with id {}", key_id));
+        }
+        info!("{} revoked key {}", user.username, key_id);
+        Ok(())
+    }
+
+    pub async fn add_input_history(&self, user: &User, input: &str) -> Result<(), anyhow::Error> {
+        self.storage
+            .add_input_history(&user.username, input, INPUT_HISTORY_LIMIT)
+            .await
+    }
+
+    pub async fn get_input_history(&self, user: &User) -> Result<Vec<String>, anyhow::Error> {
+        self.storage
+            .get_input_history(&user.username, INPUT_HISTORY_LIMIT)
+            .await
+    }
+
     pub async fn get_users(&self) -> Vec<User> {
         self.users.lock().await.clone()
     }
+
+    /// Statuses of connected users, by username.
+    pub async fn get_statuses(&self) -> HashMap<String, UserStatus> {
+        self.activity
+            .lock()
+            .await
+            .iter()
+            .map(|(username, activity)| (username.clone(), activity.status.clone()))
+            .collect()
+    }
+
+    /// Notes that `user` typed something, which brings them back from idle.
+    pub async fn record_activity(&self, user: &User) {
+        let mut activity = self.activity.lock().await;
+        let Some(activity) = activity.get_mut(&user.username) else {
+            return;
+        };
+        activity.last_input = Instant::now();
+        if activity.status.presence == Presence::Idle {
+            activity.status.presence = Presence::Online;
+            self.publish_status(&user.username, &activity.status);
+        }
+    }
+
+    /// Sets `user` away, or back online.
+    pub async fn set_away(&self, user: &User, away: bool) {
+        let mut activity = self.activity.lock().await;
+        if let Some(activity) = activity.get_mut(&user.username) {
+            activity.status.presence = if away {
+                Presence::Away
+            } else {
+                Presence::Online
+            };
+            self.publish_status(&user.username, &activity.status);
+        }
+    }
+
+    /// Sets or clears the custom status of `user`.
+    pub async fn set_status_text(
+        &self,
+        user: &User,
+        text: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if text.is_some_and(|text| text.chars().count() > MAX_STATUS_LENGTH) {
+            return Err(anyhow::anyhow!(
+                "Statuses can be at most {} characters",
+                MAX_STATUS_LENGTH
+            ));
+        }
+        self.storage.set_status_text(&user.username, text).await?;
+        let mut activity = self.activity.lock().await;
+        if let Some(activity) = activity.get_mut(&user.username) {
+            activity.status.text = text.map(str::to_string);
+            self.publish_status(&user.username, &activity.status);
+        }
+        Ok(())
+    }
+
+    /// Marks users who haven't typed anything for `idle_after` as idle.
+    pub async fn mark_idle_users(&self) {
+        let idle_after = self.config.idle_after();
+        for (username, activity) in self.activity.lock().await.iter_mut() {
+            if activity.status.presence == Presence::Online
+                && activity.last_input.elapsed() >= idle_after
+            {
+                activity.status.presence = Presence::Idle;
+                self.publish_status(username, &activity.status);
+            }
+        }
+    }
+
+    /// Starts or stops showing `user` as typing in a channel. Others are only
+    /// told when this changes, not on every keystroke.
+    pub async fn set_typing(&self, user: &User, channel_id: i64, typing: bool) {
+        let mut typers = self.typing.lock().await;
+        let previous = typers.remove(&user.username);
+        if let Some(previous) = &previous
+            && (!typing || previous.channel_id != channel_id)
+        {
+            self.publish_typing(&user.username, previous.channel_id, false);
+        }
+        if typing {
+            if previous.is_none_or(|previous| previous.channel_id != channel_id) {
+                self.publish_typing(&user.username, channel_id, true);
+            }
+            typers.insert(
+                user.username.clone(),
+                Typing {
+                    channel_id,
+                    last_input: Instant::now(),
+                },
+            );
+        }
+    }
+
+    /// Stops showing users as typing once they haven't typed for a few seconds.
+    pub async fn expire_typing(&self) {
+        self.typing.lock().await.retain(|username, typing| {
+            let expired = typing.last_input.elapsed() >= TYPING_TIMEOUT;
+            if expired {
+                self.publish_typing(username, typing.channel_id, false);
+            }
+            !expired
+        });
+    }
+
+    fn publish_typing(&self, username: &str, channel_id: i64, typing: bool) {
+        self.publish(ServerEvent::Typing {
+            username: username.to_string(),
+            channel_id,
+            typing,
+        });
+    }
+
+    fn publish_status(&self, username: &str, status: &UserStatus) {
+        self.publish(ServerEvent::StatusChanged {
+            username: username.to_string(),
+            status: status.clone(),
+        });
+    }
+}
+
+/// Hashes a password with Argon2 for storing. Empty passwords are refused.
+fn hash_password(password: &str) -> Result<String, anyhow::Error> {
+    if password.is_empty() {
+        return Err(anyhow::anyhow!("Password must not be empty"));
+    }
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_e| anyhow::anyhow!("Failed to hash password"))?;
+    Ok(password_hash.to_string())
 }
 
+#[derive(Clone)]
 pub struct AppServer {
     controller: Arc<AppServerController>,
 }
 
 impl AppServer {
-    pub async fn run(address: IpAddr, port: u16, pem: PrivateKey) -> Result<(), anyhow::Error> {
-        let server_controller = AppServerController::new().await?;
+    /// Serves clients on every configured address, with the slash commands
+    /// in `commands`, e.g. `app_commands::builtin_commands()`.
+    pub async fn run(
+        config: ServerConfig,
+        keys: Vec<PrivateKey>,
+        commands: CommandRegistry,
+    ) -> Result<(), anyhow::Error> {
+        let listen = config.listen.clone();
+        let ssh_config = Arc::new(Config {
+            inactivity_timeout: Some(config.inactivity_timeout()),
+            auth_rejection_time: config.auth_rejection_time(),
+            auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
+            keys,
+            nodelay: true,
+            ..Default::default()
+        });
 
-        let mut server = Self {
+        let server_controller = AppServerController::new(config, commands).await?;
+        let server = Self {
             controller: Arc::new(server_controller),
         };
 
+        tokio::task::spawn_blocking(highlight::preload);
+
         let controller = Arc::clone(&server.controller);
         tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HOUSEKEEPING_INTERVAL);
             loop {
-                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-
-                let mut disconnected_clients = Vec::<usize>::new();
-
-                for (client_id, app_controller) in controller.clients.lock().await.iter_mut() {
-                    let mut app_controller = app_controller.lock().await;
-                    app_controller.draw().await.unwrap();
-
-                    if !app_controller.active {
-                        info!("{} disconnected", app_controller.app_state.user.username);
-                        disconnected_clients.push(*client_id);
-                    }
-                }
-
-                let mut clients = controller.clients.lock().await;
-
-                for client_id in disconnected_clients {
-                    clients.remove(&client_id);
-                }
-
-                let mut users = controller.users.lock().await;
-                users.clear();
-                for (_, app_controller) in clients.iter_mut() {
-                    let app_controller = app_controller.lock().await;
-                    users.push(app_controller.app_state.user.clone());
-                }
+                interval.tick().await;
+                controller.mark_idle_users().await;
+                controller.expire_typing().await;
             }
         });
 
-        let config = Config {
-            inactivity_timeout: Some(std::time::Duration::from_secs(3600)),
-            auth_rejection_time: std::time::Duration::from_secs(3),
-            auth_rejection_time_initial: Some(std::time::Duration::from_secs(0)),
-            keys: vec![pem],
-            nodelay: true,
-            ..Default::default()
-        };
+        let mut listeners = JoinSet::new();
+        for address in listen {
+            info!("Listening on {}", address);
+            let mut server = server.clone();
+            let ssh_config = Arc::clone(&ssh_config);
+            listeners.spawn(async move { server.run_on_address(ssh_config, address).await });
+        }
 
-        server
-            .run_on_address(Arc::new(config), (address, port))
-            .await?;
+        // Listeners only return on failure, so the first one to finish stops the server.
+        if let Some(result) = listeners.join_next().await {
+            result??;
+        }
         Ok(())
     }
 }
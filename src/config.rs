@@ -0,0 +1,102 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+/// Who may create an account by logging in with an unknown username.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum RegistrationPolicy {
+    /// Anyone can sign up by picking a username and password on first login.
+    #[default]
+    Open,
+    /// New users need an invite code from an admin to sign up.
+    InviteOnly,
+    /// Only existing accounts, or ones created by an admin, can log in.
+    Closed,
+}
+
+/// Server settings, read from a TOML file. Every field is optional and falls
+/// back to the value in `Default`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// SQLite database file.
+    pub db_path: PathBuf,
+    /// Postgres connection URL. When set, it is used instead of `db_path`.
+    /// Requires the `postgres` cargo feature.
+    pub postgres_url: Option<String>,
+    /// Addresses to accept SSH connections on.
+    pub listen: Vec<SocketAddr>,
+    /// Host private keys in OpenSSH format.
+    pub host_keys: Vec<PathBuf>,
+    /// Seconds without traffic after which a connection is closed.
+    pub inactivity_timeout: u64,
+    /// Seconds to wait before answering a rejected authentication attempt.
+    pub auth_rejection_time: u64,
+    /// Seconds without input after which a user is shown as idle.
+    pub idle_after: u64,
+    /// Most messages of a channel a client keeps loaded while scrolling back.
+    pub history_limit: usize,
+    /// Number of messages loaded at once, on opening a channel and on
+    /// reaching the oldest or newest loaded message.
+    pub history_page: usize,
+    pub registration: RegistrationPolicy,
+    /// Usernames allowed to create invites and accounts.
+    pub admins: Vec<String>,
+    /// Ring the terminal bell when someone mentions you. Users can turn it
+    /// off for their session with `/bell off`.
+    pub mention_bell: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            db_path: PathBuf::from("sshlack.db"),
+            postgres_url: None,
+            listen: vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 2222)],
+            host_keys: vec![PathBuf::from("sshlack_key")],
+            inactivity_timeout: 3600,
+            auth_rejection_time: 3,
+            idle_after: 600,
+            history_limit: 1000,
+            history_page: 100,
+            registration: RegistrationPolicy::Open,
+            admins: Vec::new(),
+            mention_bell: true,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("Failed to parse {}: {}", path.display(), e))
+    }
+
+    pub fn inactivity_timeout(&self) -> Duration {
+        Duration::from_secs(self.inactivity_timeout)
+    }
+
+    pub fn auth_rejection_time(&self) -> Duration {
+        Duration::from_secs(self.auth_rejection_time)
+    }
+
+    pub fn idle_after(&self) -> Duration {
+        Duration::from_secs(self.idle_after)
+    }
+
+    /// Messages loaded at once, at least one.
+    pub fn history_page(&self) -> usize {
+        self.history_page.max(1)
+    }
+
+    /// Messages a client keeps loaded, at least a page.
+    pub fn history_limit(&self) -> usize {
+        self.history_limit.max(self.history_page())
+    }
+}
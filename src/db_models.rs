@@ -1,3 +1,7 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{DateTime, Utc};
+use russh::keys::{HashAlg, PublicKey};
+
 #[derive(Clone)]
 pub struct User {
     pub username: String,
@@ -26,13 +30,274 @@ impl User {
     }
 }
 
+#[derive(Clone)]
 pub struct Message {
+    /// Database id, or 0 for local notices that were never stored.
+    pub id: i64,
     pub content: String,
     pub sender: User,
+    pub channel_id: i64,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted: bool,
+    /// Message this one replies to in a thread. Replies are left out of the
+    /// channel's main list.
+    pub parent_id: Option<i64>,
+    /// Replies to this message that aren't deleted.
+    pub reply_count: usize,
 }
 
 impl Message {
-    pub fn new(content: String, sender: User) -> Self {
-        Self { content, sender }
+    pub fn new(content: String, sender: User, channel_id: i64) -> Self {
+        Self {
+            id: 0,
+            content,
+            sender,
+            channel_id,
+            created_at: Utc::now(),
+            edited_at: None,
+            deleted: false,
+            parent_id: None,
+            reply_count: 0,
+        }
+    }
+
+    /// Sent with `/me`, e.g. `waves` for `/me waves`. Typed input starting
+    /// with `/` is never sent as is, so only `/me` produces this prefix.
+    pub fn action(&self) -> Option<&str> {
+        self.content.strip_prefix("/me ")
+    }
+
+    /// Usernames mentioned as `@username`, without duplicates.
+    pub fn mentions(&self) -> Vec<&str> {
+        let mut mentions: Vec<&str> = Vec::new();
+        for (start, end) in mention_ranges(&self.content) {
+            let username = &self.content[start + 1..end];
+            if !mentions.contains(&username) {
+                mentions.push(username);
+            }
+        }
+        mentions
+    }
+}
+
+/// Byte ranges of `@username` mentions in `text`, including the `@`. A
+/// mention starts at a word boundary and doesn't include trailing dots, as
+/// in "thanks @bob."
+pub fn mention_ranges(text: &str) -> Vec<(usize, usize)> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-' || c == '.';
+    let mut ranges = Vec::new();
+    let mut previous = None;
+    for (start, c) in text.char_indices() {
+        let at_boundary = previous.is_none_or(|p: char| !is_name_char(p) && p != '@');
+        previous = Some(c);
+        if c != '@' || !at_boundary {
+            continue;
+        }
+        let name = &text[start + 1..];
+        let length = name.find(|c| !is_name_char(c)).unwrap_or(name.len());
+        let length = name[..length].trim_end_matches('.').len();
+        if length > 0 {
+            ranges.push((start, start + 1 + length));
+        }
+    }
+    ranges
+}
+
+/// A message that mentions a user, and whether the user has seen it.
+pub struct Mention {
+    pub message: Message,
+    pub seen: bool,
+}
+
+/// An emoji a user reacted to a message with.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub message_id: i64,
+    pub username: String,
+    pub emoji: String,
+}
+
+/// Whether a connected user is around.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Presence {
+    #[default]
+    Online,
+    /// No input for a while. Set and cleared automatically.
+    Idle,
+    /// Set with `/away` and cleared with `/back`.
+    Away,
+}
+
+/// What others see about a connected user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UserStatus {
+    pub presence: Presence,
+    /// Custom status set with `/status`, kept between sessions.
+    pub text: Option<String>,
+}
+
+/// How far a user has read a channel they are a member of.
+pub struct ReadMarker {
+    pub channel_id: i64,
+    /// Newest message the user has seen, or 0.
+    pub last_read_id: i64,
+    /// Messages from others after `last_read_id`.
+    pub unread: usize,
+}
+
+#[derive(Clone)]
+pub struct Channel {
+    pub id: i64,
+    pub name: String,
+    /// Direct conversations are only visible to their members. Their name is
+    /// built by `direct_name` and never shown.
+    pub direct: bool,
+    pub topic: Option<String>,
+    /// Sorted members of a direct conversation. Empty for channels.
+    pub members: Vec<String>,
+}
+
+impl Channel {
+    pub fn new(id: i64, name: String, direct: bool) -> Self {
+        Self {
+            id,
+            name,
+            direct,
+            topic: None,
+            members: Vec::new(),
+        }
+    }
+
+    /// The members of a direct conversation between `usernames`, sorted and
+    /// without duplicates.
+    pub fn direct_members(usernames: &[String]) -> Vec<String> {
+        let mut usernames = usernames.to_vec();
+        usernames.sort();
+        usernames.dedup();
+        usernames
+    }
+
+    /// Unique name of the direct conversation between `usernames`. It starts
+    /// with `@`, which channel names can't contain, and escapes `,` and `\`
+    /// so that no two sets of members share a name.
+    pub fn direct_name(usernames: &[String]) -> String {
+        let members: Vec<String> = Self::direct_members(usernames)
+            .iter()
+            .map(|member| member.replace('\\', "\\\\").replace(',', "\\,"))
+            .collect();
+        format!("@{}", members.join(","))
+    }
+
+    /// Name shown in the UI: `#name` for channels, `@other, @people` for direct conversations.
+    pub fn display_name(&self, viewer: &User) -> String {
+        if !self.direct {
+            return format!("#{}", self.name);
+        }
+        let others: Vec<String> = self
+            .members
+            .iter()
+            .filter(|member| **member != viewer.username)
+            .map(|member| format!("@{}", member))
+            .collect();
+        if others.is_empty() {
+            format!("@{}", viewer.username)
+        } else {
+            others.join(", ")
+        }
+    }
+
+    /// Normalises user input such as `#Ops` into a channel name, rejecting
+    /// anything that is not a short run of letters, digits, `-` or `_`.
+    pub fn parse_name(input: &str) -> Option<String> {
+        let name = input.trim().trim_start_matches('#').to_lowercase();
+        let valid = !name.is_empty()
+            && name.len() <= 32
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then_some(name)
+    }
+}
+
+#[derive(Clone)]
+pub struct UserKey {
+    pub id: i64,
+    pub public_key: String,
+    pub comment: String,
+}
+
+impl UserKey {
+    /// Encodes a key as `algorithm base64`, dropping the comment, which is
+    /// the form keys are stored and compared in.
+    pub fn encode(key: &PublicKey) -> Result<String, anyhow::Error> {
+        let key = PublicKey::new(key.key_data().clone(), "");
+        Ok(key.to_openssh()?.trim().to_string())
+    }
+
+    pub fn fingerprint(&self) -> String {
+        PublicKey::from_openssh(&self.public_key)
+            .map(|key| key.fingerprint(HashAlg::Sha256).to_string())
+            .unwrap_or_else(|_| "invalid key".to_string())
+    }
+}
+
+/// Characters used in invite codes, without easily confused ones like 0/o and 1/l.
+const INVITE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+const INVITE_LENGTH: usize = 10;
+
+#[derive(Clone)]
+pub struct Invite {
+    pub code: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Single-use invites stop working once redeemed; others work until they expire.
+    pub single_use: bool,
+    pub uses: i64,
+}
+
+impl Invite {
+    /// Creates a random invite. Invites without an expiry are single-use.
+    pub fn generate(created_by: &User, expires_at: Option<DateTime<Utc>>) -> Self {
+        let code = (0..INVITE_LENGTH)
+            .map(|_| {
+                let index = OsRng.next_u32() as usize % INVITE_ALPHABET.len();
+                INVITE_ALPHABET[index] as char
+            })
+            .collect();
+        Self {
+            code,
+            created_by: created_by.username.clone(),
+            created_at: Utc::now(),
+            expires_at,
+            single_use: expires_at.is_none(),
+            uses: 0,
+        }
+    }
+
+    pub fn is_valid(&self, now: DateTime<Utc>) -> bool {
+        !(self.single_use && self.uses > 0) && self.expires_at.is_none_or(|expires| expires > now)
+    }
+}
+
+/// Filters of a message search. Unset filters match every message.
+#[derive(Clone, Default)]
+pub struct MessageSearch {
+    /// Words that must all appear in a message, each matching as a prefix.
+    pub words: Vec<String>,
+    pub sender: Option<String>,
+    pub channel_id: Option<i64>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+}
+
+impl MessageSearch {
+    /// Splits free text into lowercase words the same way every backend does.
+    pub fn split_words(text: &str) -> Vec<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect()
     }
 }
@@ -1,3 +1,6 @@
 pub mod app;
 pub mod app_server;
+pub mod config;
 pub mod db_models;
+pub mod server_events;
+pub mod storage;
@@ -1,10 +1,11 @@
+use sshlack::app::app_commands::builtin_commands;
 use sshlack::app_server::AppServer;
+use sshlack::config::{RegistrationPolicy, ServerConfig};
 
-use env_logger;
 use log::{error, info};
 
 use clap::Parser;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
 use russh::keys::PrivateKey;
@@ -13,34 +14,128 @@ use russh::keys::PrivateKey;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// IP to listen on
-    #[arg(short, long, default_value_t = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)))]
-    address: IpAddr,
+    /// TOML config file. Flags below override its values
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-    /// Port to listen on
-    #[arg(short, long, default_value_t = 2222)]
-    port: u16,
+    /// IP to listen on [default: 0.0.0.0]
+    #[arg(short, long)]
+    address: Option<IpAddr>,
 
-    /// Certificate file path
-    #[arg(short, long, default_value = "sshlack_key")]
-    cert_path: PathBuf,
+    /// Port to listen on [default: 2222]
+    #[arg(short, long)]
+    port: Option<u16>,
+
+    /// Certificate file path [default: sshlack_key]
+    #[arg(short, long)]
+    cert_path: Option<PathBuf>,
+
+    /// Database file path [default: sshlack.db]
+    #[arg(long)]
+    db_path: Option<PathBuf>,
+
+    /// Postgres URL to use instead of the SQLite file (needs the `postgres` feature)
+    #[arg(long)]
+    postgres_url: Option<String>,
+
+    /// Seconds of inactivity before a connection is closed [default: 3600]
+    #[arg(long)]
+    inactivity_timeout: Option<u64>,
+
+    /// Seconds to wait before rejecting a failed login [default: 3]
+    #[arg(long)]
+    auth_rejection_time: Option<u64>,
+
+    /// Most messages of a channel a client keeps loaded [default: 1000]
+    #[arg(long)]
+    history_limit: Option<usize>,
+
+    /// Number of messages loaded at once when scrolling [default: 100]
+    #[arg(long)]
+    history_page: Option<usize>,
+
+    /// Who may create new accounts [default: open]
+    #[arg(long, value_enum)]
+    registration: Option<RegistrationPolicy>,
+
+    /// Username allowed to create invites and accounts, can be repeated
+    #[arg(long = "admin")]
+    admins: Vec<String>,
+}
+
+impl Args {
+    fn into_config(self) -> Result<ServerConfig, anyhow::Error> {
+        let mut config = match &self.config {
+            Some(path) => ServerConfig::load(path)?,
+            None => ServerConfig::default(),
+        };
+
+        if self.address.is_some() || self.port.is_some() {
+            let default = config
+                .listen
+                .first()
+                .copied()
+                .unwrap_or_else(|| ServerConfig::default().listen[0]);
+            config.listen = vec![SocketAddr::new(
+                self.address.unwrap_or(default.ip()),
+                self.port.unwrap_or(default.port()),
+            )];
+        }
+        if let Some(cert_path) = self.cert_path {
+            config.host_keys = vec![cert_path];
+        }
+        if let Some(db_path) = self.db_path {
+            config.db_path = db_path;
+        }
+        if let Some(postgres_url) = self.postgres_url {
+            config.postgres_url = Some(postgres_url);
+        }
+        if let Some(inactivity_timeout) = self.inactivity_timeout {
+            config.inactivity_timeout = inactivity_timeout;
+        }
+        if let Some(auth_rejection_time) = self.auth_rejection_time {
+            config.auth_rejection_time = auth_rejection_time;
+        }
+        if let Some(history_limit) = self.history_limit {
+            config.history_limit = history_limit;
+        }
+        if let Some(history_page) = self.history_page {
+            config.history_page = history_page;
+        }
+        if let Some(registration) = self.registration {
+            config.registration = registration;
+        }
+        if !self.admins.is_empty() {
+            config.admins = self.admins;
+        }
+        Ok(config)
+    }
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
-    let args = Args::parse();
-
-    match PrivateKey::read_openssh_file(args.cert_path.as_path()) {
-        Ok(pem) => {
-            info!("Starting sshlack server on {}:{}", args.address, args.port);
-            AppServer::run(args.address, args.port, pem)
-                .await
-                .expect("Failed running server");
-        }
+    let config = match Args::parse().into_config() {
+        Ok(config) => config,
         Err(e) => {
-            error!("Error loading certificate: {}", e);
+            error!("Error loading config: {}", e);
             std::process::exit(1);
         }
+    };
+
+    let mut keys = Vec::new();
+    for path in &config.host_keys {
+        match PrivateKey::read_openssh_file(path) {
+            Ok(pem) => keys.push(pem),
+            Err(e) => {
+                error!("Error loading certificate {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
     }
+
+    info!("Starting sshlack server");
+    AppServer::run(config, keys, builtin_commands())
+        .await
+        .expect("Failed running server");
 }
@@ -0,0 +1,35 @@
+use crate::db_models::{Channel, Message, Reaction, User, UserStatus};
+
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Clone)]
+pub enum ServerEvent {
+    NewMessage(Message),
+    MessageUpdated(Message),
+    /// All reactions to a message after one was added or removed.
+    ReactionsChanged {
+        message_id: i64,
+        reactions: Vec<Reaction>,
+    },
+    UserJoined(User),
+    UserLeft(User),
+    DirectChannelOpened(Channel),
+    NicknameChanged {
+        username: String,
+        nickname: Option<String>,
+    },
+    TopicChanged {
+        channel_id: i64,
+        topic: Option<String>,
+        set_by: User,
+    },
+    Typing {
+        username: String,
+        channel_id: i64,
+        typing: bool,
+    },
+    StatusChanged {
+        username: String,
+        status: UserStatus,
+    },
+}
@@ -0,0 +1,744 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    db_models::{Channel, Invite, Mention, Message, MessageSearch, Reaction, ReadMarker, UserKey},
+    storage::Storage,
+};
+
+struct StoredUser {
+    username: String,
+    password_hash: String,
+    nickname: Option<String>,
+    status: Option<String>,
+}
+
+struct StoredKey {
+    username: String,
+    key: UserKey,
+}
+
+#[derive(Default)]
+struct MemoryData {
+    users: Vec<StoredUser>,
+    keys: Vec<StoredKey>,
+    invites: Vec<Invite>,
+    channels: Vec<Channel>,
+    channel_members: Vec<(i64, String)>,
+    /// `(channel_id, username, last_read_id)`
+    read_markers: Vec<(i64, String, i64)>,
+    messages: Vec<Message>,
+    input_history: Vec<(String, String)>,
+    reactions: Vec<Reaction>,
+    /// `(message_id, username, seen)`
+    mentions: Vec<(i64, String, bool)>,
+    last_id: i64,
+}
+
+impl MemoryData {
+    /// Ids are shared between tables, which keeps them unique and increasing
+    /// like the SQLite autoincrement columns.
+    fn next_id(&mut self) -> i64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    fn is_member(&self, channel_id: i64, username: &str) -> bool {
+        self.channel_members
+            .iter()
+            .any(|(id, member)| *id == channel_id && member == username)
+    }
+
+    /// A copy of `channel` with the members of a direct conversation filled in.
+    fn with_members(&self, channel: &Channel) -> Channel {
+        let mut members: Vec<String> = self
+            .channel_members
+            .iter()
+            .filter(|(id, _)| channel.direct && *id == channel.id)
+            .map(|(_, member)| member.clone())
+            .collect();
+        members.sort();
+        Channel {
+            members,
+            ..channel.clone()
+        }
+    }
+
+    /// A copy of `message` with its replies counted, as the databases return it.
+    fn counted(&self, message: &Message) -> Message {
+        Message {
+            reply_count: self
+                .messages
+                .iter()
+                .filter(|reply| reply.parent_id == Some(message.id) && !reply.deleted)
+                .count(),
+            ..message.clone()
+        }
+    }
+}
+
+/// Storage that keeps everything in process memory. Intended for tests.
+#[derive(Default)]
+pub struct MemoryStorage {
+    data: Mutex<MemoryData>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn data(&self) -> std::sync::MutexGuard<'_, MemoryData> {
+        // Nothing panics while holding the lock, but a test failure elsewhere
+        // should not poison every later call.
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
+
+    async fn get_password_hash(&self, username: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .map(|user| user.password_hash.clone()))
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        if data.users.iter().any(|user| user.username == username) {
+            return Err(anyhow::anyhow!("User {} already exists", username));
+        }
+        data.users.push(StoredUser {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            nickname: None,
+            status: None,
+        });
+        Ok(())
+    }
+
+    async fn create_first_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<bool, anyhow::Error> {
+        if !self.data().users.is_empty() {
+            return Ok(false);
+        }
+        self.create_user(username, password_hash).await?;
+        Ok(true)
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, anyhow::Error> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .any(|user| user.username == username))
+    }
+
+    async fn set_nickname(
+        &self,
+        username: &str,
+        nickname: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(user) = self
+            .data()
+            .users
+            .iter_mut()
+            .find(|user| user.username == username)
+        {
+            user.nickname = nickname.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    async fn set_status_text(
+        &self,
+        username: &str,
+        text: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(user) = self
+            .data()
+            .users
+            .iter_mut()
+            .find(|user| user.username == username)
+        {
+            user.status = text.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    async fn get_status_text(&self, username: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .and_then(|user| user.status.clone()))
+    }
+
+    async fn get_nicknames(&self) -> Result<Vec<(String, String)>, anyhow::Error> {
+        Ok(self
+            .data()
+            .users
+            .iter()
+            .filter_map(|user| {
+                let nickname = user.nickname.clone()?;
+                Some((user.username.clone(), nickname))
+            })
+            .collect())
+    }
+
+    async fn find_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mut usernames: Vec<String> = self
+            .data()
+            .users
+            .iter()
+            .filter(|user| user.username.starts_with(prefix))
+            .map(|user| user.username.clone())
+            .collect();
+        usernames.sort();
+        usernames.truncate(limit);
+        Ok(usernames)
+    }
+
+    async fn key_exists(&self, public_key: &str) -> Result<bool, anyhow::Error> {
+        Ok(self
+            .data()
+            .keys
+            .iter()
+            .any(|stored| stored.key.public_key == public_key))
+    }
+
+    async fn user_has_key(&self, username: &str, public_key: &str) -> Result<bool, anyhow::Error> {
+        Ok(self
+            .data()
+            .keys
+            .iter()
+            .any(|stored| stored.username == username && stored.key.public_key == public_key))
+    }
+
+    async fn add_user_key(
+        &self,
+        username: &str,
+        public_key: &str,
+        comment: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        if data
+            .keys
+            .iter()
+            .any(|stored| stored.key.public_key == public_key)
+        {
+            return Err(anyhow::anyhow!("Key already exists"));
+        }
+        if !data.users.iter().any(|user| user.username == username) {
+            return Ok(());
+        }
+        let id = data.next_id();
+        data.keys.push(StoredKey {
+            username: username.to_string(),
+            key: UserKey {
+                id,
+                public_key: public_key.to_string(),
+                comment: comment.to_string(),
+            },
+        });
+        Ok(())
+    }
+
+    async fn get_user_keys(&self, username: &str) -> Result<Vec<UserKey>, anyhow::Error> {
+        Ok(self
+            .data()
+            .keys
+            .iter()
+            .filter(|stored| stored.username == username)
+            .map(|stored| stored.key.clone())
+            .collect())
+    }
+
+    async fn delete_user_key(&self, username: &str, key_id: i64) -> Result<bool, anyhow::Error> {
+        let mut data = self.data();
+        let count = data.keys.len();
+        data.keys
+            .retain(|stored| !(stored.username == username && stored.key.id == key_id));
+        Ok(data.keys.len() < count)
+    }
+
+    async fn create_invite(&self, invite: &Invite) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        if data.invites.iter().any(|stored| stored.code == invite.code) {
+            return Err(anyhow::anyhow!("Invite {} already exists", invite.code));
+        }
+        data.invites.push(invite.clone());
+        Ok(())
+    }
+
+    async fn get_invites(&self) -> Result<Vec<Invite>, anyhow::Error> {
+        Ok(self.data().invites.clone())
+    }
+
+    async fn delete_invite(&self, code: &str) -> Result<bool, anyhow::Error> {
+        let mut data = self.data();
+        let count = data.invites.len();
+        data.invites.retain(|invite| invite.code != code);
+        Ok(data.invites.len() < count)
+    }
+
+    async fn create_user_with_invite(
+        &self,
+        username: &str,
+        password_hash: &str,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut data = self.data();
+        if data.users.iter().any(|user| user.username == username) {
+            return Err(anyhow::anyhow!("User {} already exists", username));
+        }
+        let Some(invite) = data
+            .invites
+            .iter_mut()
+            .find(|invite| invite.code == code && invite.is_valid(now))
+        else {
+            return Ok(false);
+        };
+        invite.uses += 1;
+        data.users.push(StoredUser {
+            username: username.to_string(),
+            password_hash: password_hash.to_string(),
+            nickname: None,
+            status: None,
+        });
+        Ok(true)
+    }
+
+    async fn get_channel(&self, name: &str) -> Result<Option<Channel>, anyhow::Error> {
+        let data = self.data();
+        Ok(data
+            .channels
+            .iter()
+            .find(|channel| channel.name == name)
+            .map(|channel| data.with_members(channel)))
+    }
+
+    async fn create_channel(
+        &self,
+        name: &str,
+        _created_by: &str,
+        direct: bool,
+        members: &[&str],
+    ) -> Result<Channel, anyhow::Error> {
+        let mut data = self.data();
+        if data.channels.iter().any(|channel| channel.name == name) {
+            return Err(anyhow::anyhow!("Channel {} already exists", name));
+        }
+        let channel = Channel::new(data.next_id(), name.to_string(), direct);
+        data.channels.push(channel.clone());
+        for member in members {
+            if !data.is_member(channel.id, member) {
+                data.channel_members.push((channel.id, member.to_string()));
+            }
+        }
+        Ok(data.with_members(&channel))
+    }
+
+    async fn add_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        if !data.is_member(channel_id, username) {
+            data.channel_members
+                .push((channel_id, username.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn remove_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        data.channel_members
+            .retain(|(id, member)| !(*id == channel_id && member == username));
+        data.read_markers
+            .retain(|(id, member, _)| !(*id == channel_id && member == username));
+        Ok(())
+    }
+
+    async fn get_channel_members(&self, channel_id: i64) -> Result<Vec<String>, anyhow::Error> {
+        let mut members: Vec<String> = self
+            .data()
+            .channel_members
+            .iter()
+            .filter(|(id, _)| *id == channel_id)
+            .map(|(_, member)| member.clone())
+            .collect();
+        members.sort();
+        Ok(members)
+    }
+
+    async fn set_channel_topic(
+        &self,
+        channel_id: i64,
+        topic: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        if let Some(channel) = self
+            .data()
+            .channels
+            .iter_mut()
+            .find(|channel| channel.id == channel_id)
+        {
+            channel.topic = topic.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    async fn get_user_channels(&self, username: &str) -> Result<Vec<Channel>, anyhow::Error> {
+        let data = self.data();
+        let mut channels: Vec<Channel> = data
+            .channels
+            .iter()
+            .filter(|channel| data.is_member(channel.id, username))
+            .map(|channel| data.with_members(channel))
+            .collect();
+        channels.sort_by(|a, b| (a.direct, &a.name).cmp(&(b.direct, &b.name)));
+        Ok(channels)
+    }
+
+    async fn insert_message(&self, message: &Message) -> Result<i64, anyhow::Error> {
+        let mut data = self.data();
+        let mut message = message.clone();
+        message.id = data.next_id();
+        data.messages.push(message.clone());
+        Ok(message.id)
+    }
+
+    async fn get_messages(
+        &self,
+        channel_id: i64,
+        username: &str,
+        before_id: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let data = self.data();
+        let Some(channel) = data.channels.iter().find(|c| c.id == channel_id) else {
+            return Ok(Vec::new());
+        };
+        if channel.direct && !data.is_member(channel_id, username) {
+            return Ok(Vec::new());
+        }
+        let mut messages: Vec<Message> = data
+            .messages
+            .iter()
+            .rev()
+            .filter(|message| message.channel_id == channel_id && message.parent_id.is_none())
+            .filter(|message| before_id.is_none_or(|before_id| message.id < before_id))
+            .take(limit)
+            .map(|message| data.counted(message))
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    async fn get_messages_around(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let messages = self
+            .get_messages(channel_id, username, None, usize::MAX)
+            .await?;
+        let start = messages.partition_point(|message| message.id < message_id);
+        let first = start.saturating_sub(before);
+        let last = messages
+            .len()
+            .min(start.saturating_add(after).saturating_add(1));
+        Ok(messages[first..last].to_vec())
+    }
+
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let data = self.data();
+        Ok(data
+            .messages
+            .iter()
+            .rev()
+            .filter(|message| {
+                let words = MessageSearch::split_words(&message.content);
+                !message.deleted
+                    && data.is_member(message.channel_id, username)
+                    && search
+                        .words
+                        .iter()
+                        .all(|wanted| words.iter().any(|word| word.starts_with(wanted.as_str())))
+                    && search
+                        .sender
+                        .as_ref()
+                        .is_none_or(|sender| *sender == message.sender.username)
+                    && search.channel_id.is_none_or(|id| id == message.channel_id)
+                    && search.after.is_none_or(|after| message.created_at >= after)
+                    && search
+                        .before
+                        .is_none_or(|before| message.created_at < before)
+            })
+            .take(limit)
+            .map(|message| data.counted(message))
+            .collect())
+    }
+
+    async fn get_message(&self, message_id: i64) -> Result<Option<Message>, anyhow::Error> {
+        let data = self.data();
+        Ok(data
+            .messages
+            .iter()
+            .find(|message| message.id == message_id)
+            .map(|message| data.counted(message)))
+    }
+
+    async fn get_replies(&self, parent_id: i64) -> Result<Vec<Message>, anyhow::Error> {
+        Ok(self
+            .data()
+            .messages
+            .iter()
+            .filter(|message| message.parent_id == Some(parent_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn update_message(
+        &self,
+        message_id: i64,
+        sender: &str,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut data = self.data();
+        let Some(message) = data.messages.iter_mut().find(|message| {
+            message.id == message_id && message.sender.username == sender && !message.deleted
+        }) else {
+            return Ok(false);
+        };
+        message.content = content.to_string();
+        message.edited_at = Some(edited_at);
+        Ok(true)
+    }
+
+    async fn delete_message(&self, message_id: i64, sender: &str) -> Result<bool, anyhow::Error> {
+        let mut data = self.data();
+        let Some(message) = data.messages.iter_mut().find(|message| {
+            message.id == message_id && message.sender.username == sender && !message.deleted
+        }) else {
+            return Ok(false);
+        };
+        message.content.clear();
+        message.deleted = true;
+        Ok(true)
+    }
+
+    async fn toggle_reaction(
+        &self,
+        message_id: i64,
+        username: &str,
+        emoji: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let reaction = Reaction {
+            message_id,
+            username: username.to_string(),
+            emoji: emoji.to_string(),
+        };
+        let mut data = self.data();
+        let count = data.reactions.len();
+        data.reactions.retain(|existing| *existing != reaction);
+        if data.reactions.len() < count {
+            return Ok(false);
+        }
+        data.reactions.push(reaction);
+        Ok(true)
+    }
+
+    async fn get_reactions(&self, message_ids: &[i64]) -> Result<Vec<Reaction>, anyhow::Error> {
+        Ok(self
+            .data()
+            .reactions
+            .iter()
+            .filter(|reaction| message_ids.contains(&reaction.message_id))
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_read(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        if !data.is_member(channel_id, username) {
+            return Ok(());
+        }
+        match data
+            .read_markers
+            .iter_mut()
+            .find(|(id, member, _)| *id == channel_id && member == username)
+        {
+            Some((_, _, last_read_id)) => *last_read_id = (*last_read_id).max(message_id),
+            None => data
+                .read_markers
+                .push((channel_id, username.to_string(), message_id)),
+        }
+        Ok(())
+    }
+
+    async fn get_read_markers(&self, username: &str) -> Result<Vec<ReadMarker>, anyhow::Error> {
+        let data = self.data();
+        Ok(data
+            .channel_members
+            .iter()
+            .filter(|(_, member)| member == username)
+            .map(|(channel_id, _)| {
+                let last_read_id = data
+                    .read_markers
+                    .iter()
+                    .find(|(id, member, _)| id == channel_id && member == username)
+                    .map_or(0, |(_, _, last_read_id)| *last_read_id);
+                let unread = data
+                    .messages
+                    .iter()
+                    .filter(|message| {
+                        message.channel_id == *channel_id
+                            && message.id > last_read_id
+                            && !message.deleted
+                            && message.parent_id.is_none()
+                            && message.sender.username != username
+                    })
+                    .count();
+                ReadMarker {
+                    channel_id: *channel_id,
+                    last_read_id,
+                    unread,
+                }
+            })
+            .collect())
+    }
+
+    async fn add_mention(
+        &self,
+        message_id: i64,
+        username: &str,
+        seen: bool,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        let exists = data
+            .mentions
+            .iter()
+            .any(|(id, mentioned, _)| *id == message_id && mentioned == username);
+        if !exists {
+            data.mentions.push((message_id, username.to_string(), seen));
+        }
+        Ok(())
+    }
+
+    async fn get_mentions(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Mention>, anyhow::Error> {
+        let data = self.data();
+        let mut mentions: Vec<Mention> = data
+            .mentions
+            .iter()
+            .filter(|(_, mentioned, _)| mentioned == username)
+            .filter_map(|(message_id, _, seen)| {
+                let message = data
+                    .messages
+                    .iter()
+                    .find(|message| message.id == *message_id && !message.deleted)?;
+                Some(Mention {
+                    message: data.counted(message),
+                    seen: *seen,
+                })
+            })
+            .collect();
+        mentions.sort_by_key(|mention| std::cmp::Reverse(mention.message.id));
+        mentions.truncate(limit);
+        Ok(mentions)
+    }
+
+    async fn mark_mentions_seen(&self, username: &str) -> Result<(), anyhow::Error> {
+        for (_, mentioned, seen) in self.data().mentions.iter_mut() {
+            if mentioned == username {
+                *seen = true;
+            }
+        }
+        Ok(())
+    }
+
+    async fn add_input_history(
+        &self,
+        username: &str,
+        content: &str,
+        keep: usize,
+    ) -> Result<(), anyhow::Error> {
+        let mut data = self.data();
+        data.input_history
+            .push((username.to_string(), content.to_string()));
+        let count = data
+            .input_history
+            .iter()
+            .filter(|(owner, _)| owner == username)
+            .count();
+        let mut overflow = count.saturating_sub(keep);
+        data.input_history.retain(|(owner, _)| {
+            if owner == username && overflow > 0 {
+                overflow -= 1;
+                return false;
+            }
+            true
+        });
+        Ok(())
+    }
+
+    async fn get_input_history(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let data = self.data();
+        let mut history: Vec<String> = data
+            .input_history
+            .iter()
+            .rev()
+            .filter(|(owner, _)| owner == username)
+            .take(limit)
+            .map(|(_, content)| content.clone())
+            .collect();
+        history.reverse();
+        Ok(history)
+    }
+}
@@ -0,0 +1,216 @@
+use async_trait::async_trait;
+
+use chrono::{DateTime, Utc};
+
+use crate::db_models::{
+    Channel, Invite, Mention, Message, MessageSearch, Reaction, ReadMarker, UserKey,
+};
+
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+pub mod sqlite_migrations;
+
+pub use memory::MemoryStorage;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+/// Persistence used by `AppServerController`. Implementations only store and
+/// query data; permission checks and password hashing stay in the controller.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Brings the schema up to date. Called once on startup.
+    async fn migrate(&self) -> Result<(), anyhow::Error>;
+
+    async fn get_password_hash(&self, username: &str) -> Result<Option<String>, anyhow::Error>;
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<(), anyhow::Error>;
+    /// Creates the account only if there are no accounts yet. Returns false,
+    /// creating nothing, otherwise.
+    async fn create_first_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<bool, anyhow::Error>;
+    async fn user_exists(&self, username: &str) -> Result<bool, anyhow::Error>;
+    /// Sets or clears the name shown instead of `username`.
+    async fn set_nickname(
+        &self,
+        username: &str,
+        nickname: Option<&str>,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns `(username, nickname)` for every user with a nickname.
+    async fn get_nicknames(&self) -> Result<Vec<(String, String)>, anyhow::Error>;
+    /// Sets or clears the custom status text of `username`.
+    async fn set_status_text(
+        &self,
+        username: &str,
+        text: Option<&str>,
+    ) -> Result<(), anyhow::Error>;
+    async fn get_status_text(&self, username: &str) -> Result<Option<String>, anyhow::Error>;
+    /// Returns up to `limit` usernames starting with `prefix`, sorted.
+    async fn find_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error>;
+
+    async fn key_exists(&self, public_key: &str) -> Result<bool, anyhow::Error>;
+    async fn user_has_key(&self, username: &str, public_key: &str) -> Result<bool, anyhow::Error>;
+    async fn add_user_key(
+        &self,
+        username: &str,
+        public_key: &str,
+        comment: &str,
+    ) -> Result<(), anyhow::Error>;
+    async fn get_user_keys(&self, username: &str) -> Result<Vec<UserKey>, anyhow::Error>;
+    /// Returns false when `username` has no key with that id.
+    async fn delete_user_key(&self, username: &str, key_id: i64) -> Result<bool, anyhow::Error>;
+
+    async fn create_invite(&self, invite: &Invite) -> Result<(), anyhow::Error>;
+    async fn get_invites(&self) -> Result<Vec<Invite>, anyhow::Error>;
+    /// Returns false when no invite has that code.
+    async fn delete_invite(&self, code: &str) -> Result<bool, anyhow::Error>;
+    /// Counts a use of the invite and creates the account together, so that
+    /// a use is only counted for an account that exists. Returns false,
+    /// changing nothing, when the invite does not exist, has expired at `now`
+    /// or was already used up.
+    async fn create_user_with_invite(
+        &self,
+        username: &str,
+        password_hash: &str,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error>;
+
+    async fn get_channel(&self, name: &str) -> Result<Option<Channel>, anyhow::Error>;
+    /// Creates a channel together with its initial members.
+    async fn create_channel(
+        &self,
+        name: &str,
+        created_by: &str,
+        direct: bool,
+        members: &[&str],
+    ) -> Result<Channel, anyhow::Error>;
+    async fn add_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error>;
+    async fn remove_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error>;
+    /// Usernames of the members of a channel, sorted.
+    async fn get_channel_members(&self, channel_id: i64) -> Result<Vec<String>, anyhow::Error>;
+    async fn set_channel_topic(
+        &self,
+        channel_id: i64,
+        topic: Option<&str>,
+    ) -> Result<(), anyhow::Error>;
+    /// Channels `username` is a member of, public channels first.
+    async fn get_user_channels(&self, username: &str) -> Result<Vec<Channel>, anyhow::Error>;
+
+    /// Stores a message and returns its id.
+    async fn insert_message(&self, message: &Message) -> Result<i64, anyhow::Error>;
+    /// Returns up to `limit` most recent messages of a channel, oldest first,
+    /// leaving out replies. With `before_id`, only messages older than it are
+    /// returned. Direct conversations return nothing unless `username` is a member.
+    async fn get_messages(
+        &self,
+        channel_id: i64,
+        username: &str,
+        before_id: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error>;
+    /// Returns up to `before` messages preceding `message_id`, the message
+    /// itself and up to `after` following it, oldest first, leaving out
+    /// replies. Direct conversations return nothing unless `username` is a member.
+    async fn get_messages_around(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<Message>, anyhow::Error>;
+    /// Finds messages in channels `username` is a member of, newest first.
+    /// Deleted messages are never returned.
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error>;
+    async fn get_message(&self, message_id: i64) -> Result<Option<Message>, anyhow::Error>;
+    /// Returns the replies to a message, oldest first.
+    async fn get_replies(&self, parent_id: i64) -> Result<Vec<Message>, anyhow::Error>;
+    /// Returns false unless `sender` owns the message and it is not deleted.
+    async fn update_message(
+        &self,
+        message_id: i64,
+        sender: &str,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error>;
+    /// Returns false unless `sender` owns the message and it is not deleted.
+    async fn delete_message(&self, message_id: i64, sender: &str) -> Result<bool, anyhow::Error>;
+
+    /// Adds a reaction, or removes it if `username` already reacted to the
+    /// message with `emoji`. Returns whether it was added.
+    async fn toggle_reaction(
+        &self,
+        message_id: i64,
+        username: &str,
+        emoji: &str,
+    ) -> Result<bool, anyhow::Error>;
+    /// Returns the reactions to the given messages, oldest first.
+    async fn get_reactions(&self, message_ids: &[i64]) -> Result<Vec<Reaction>, anyhow::Error>;
+
+    /// Moves `username`'s read marker in a channel forward to `message_id`.
+    /// Markers never move back.
+    async fn mark_read(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns a read marker for every channel `username` is a member of.
+    async fn get_read_markers(&self, username: &str) -> Result<Vec<ReadMarker>, anyhow::Error>;
+
+    /// Records that `username` was mentioned in a message, once per message.
+    async fn add_mention(
+        &self,
+        message_id: i64,
+        username: &str,
+        seen: bool,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns up to `limit` most recent mentions of `username`, newest
+    /// first. Deleted messages are left out.
+    async fn get_mentions(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Mention>, anyhow::Error>;
+    async fn mark_mentions_seen(&self, username: &str) -> Result<(), anyhow::Error>;
+
+    /// Records an input `username` sent, keeping only their `keep` most recent inputs.
+    async fn add_input_history(
+        &self,
+        username: &str,
+        content: &str,
+        keep: usize,
+    ) -> Result<(), anyhow::Error>;
+    /// Returns up to `limit` most recent inputs of `username`, oldest first.
+    async fn get_input_history(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error>;
+}
+
+pub(crate) fn timestamp(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+}
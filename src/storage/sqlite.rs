@@ -0,0 +1,717 @@
+use std::{fs::OpenOptions, path::Path};
+
+use async_trait::async_trait;
+
+use sqlx::Row;
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    db_models::{
+        Channel, Invite, Mention, Message, MessageSearch, Reaction, ReadMarker, User, UserKey,
+    },
+    storage::{Storage, sqlite_migrations, timestamp},
+};
+
+pub struct SqliteStorage {
+    db_pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Opens the database file at `path`, creating it when missing.
+    pub async fn connect(path: &Path) -> Result<Self, anyhow::Error> {
+        Self::ensure_db_exists(path)?;
+        let db_pool = SqlitePool::connect(format!("sqlite://{}", path.display()).as_str()).await?;
+        Ok(Self::from_pool(db_pool))
+    }
+
+    pub fn from_pool(db_pool: SqlitePool) -> Self {
+        Self { db_pool }
+    }
+
+    /// Fills in the members of a direct conversation.
+    async fn with_members(&self, mut channel: Channel) -> Result<Channel, anyhow::Error> {
+        if channel.direct {
+            channel.members = self.get_channel_members(channel.id).await?;
+        }
+        Ok(channel)
+    }
+
+    fn ensure_db_exists(path: &Path) -> Result<(), anyhow::Error> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<(), anyhow::Error> {
+        sqlite_migrations::migrate(&self.db_pool).await
+    }
+
+    async fn get_password_hash(&self, username: &str) -> Result<Option<String>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT password_hash FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.map(|row| row.get("password_hash")))
+    }
+
+    async fn create_user(&self, username: &str, password_hash: &str) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT INTO users (username, password_hash) VALUES (?,?)")
+            .bind(username)
+            .bind(password_hash)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn create_first_user(
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let result = sqlx::query("INSERT INTO users (username, password_hash) SELECT ?, ? WHERE NOT EXISTS (SELECT 1 FROM users)")
+            .bind(username)
+            .bind(password_hash)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn user_exists(&self, username: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT 1 FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn set_nickname(
+        &self,
+        username: &str,
+        nickname: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("UPDATE users SET nickname = ? WHERE username = ?")
+            .bind(nickname)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_status_text(
+        &self,
+        username: &str,
+        text: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("UPDATE users SET status = ? WHERE username = ?")
+            .bind(text)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_status_text(&self, username: &str) -> Result<Option<String>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT status FROM users WHERE username = ?")
+            .bind(username)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.and_then(|row| row.get("status")))
+    }
+
+    async fn get_nicknames(&self) -> Result<Vec<(String, String)>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT username, nickname FROM users WHERE nickname IS NOT NULL")
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("username"), row.get("nickname")))
+            .collect())
+    }
+
+    async fn find_usernames(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT username FROM users WHERE substr(username, 1, length(?1)) = ?1 ORDER BY username LIMIT ?2")
+            .bind(prefix)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(|row| row.get("username")).collect())
+    }
+
+    async fn key_exists(&self, public_key: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT 1 FROM user_keys WHERE public_key = ?")
+            .bind(public_key)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn user_has_key(&self, username: &str, public_key: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT 1 FROM user_keys JOIN users ON users.id = user_keys.user_id WHERE users.username = ? AND user_keys.public_key = ?")
+            .bind(username)
+            .bind(public_key)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.is_some())
+    }
+
+    async fn add_user_key(
+        &self,
+        username: &str,
+        public_key: &str,
+        comment: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT INTO user_keys (user_id, public_key, comment) SELECT id, ?, ? FROM users WHERE username = ?")
+            .bind(public_key)
+            .bind(comment)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_keys(&self, username: &str) -> Result<Vec<UserKey>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT user_keys.id, public_key, comment FROM user_keys JOIN users ON users.id = user_keys.user_id WHERE users.username = ? ORDER BY user_keys.id")
+            .bind(username)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| UserKey {
+                id: row.get("id"),
+                public_key: row.get("public_key"),
+                comment: row.get("comment"),
+            })
+            .collect())
+    }
+
+    async fn delete_user_key(&self, username: &str, key_id: i64) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let result = sqlx::query("DELETE FROM user_keys WHERE id = ? AND user_id = (SELECT id FROM users WHERE username = ?)")
+            .bind(key_id)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_invite(&self, invite: &Invite) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT INTO invites (code, created_by, created_at, expires_at, single_use, uses) VALUES (?, ?, ?, ?, ?, ?)")
+            .bind(&invite.code)
+            .bind(&invite.created_by)
+            .bind(invite.created_at.timestamp())
+            .bind(invite.expires_at.map(|time| time.timestamp()))
+            .bind(invite.single_use)
+            .bind(invite.uses)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_invites(&self) -> Result<Vec<Invite>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT code, created_by, created_at, expires_at, single_use, uses FROM invites ORDER BY created_at")
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Invite {
+                code: row.get("code"),
+                created_by: row.get("created_by"),
+                created_at: timestamp(row.get("created_at")),
+                expires_at: row.get::<Option<i64>, _>("expires_at").map(timestamp),
+                single_use: row.get("single_use"),
+                uses: row.get("uses"),
+            })
+            .collect())
+    }
+
+    async fn delete_invite(&self, code: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let result = sqlx::query("DELETE FROM invites WHERE code = ?")
+            .bind(code)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn create_user_with_invite(
+        &self,
+        username: &str,
+        password_hash: &str,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let redeemed = sqlx::query("UPDATE invites SET uses = uses + 1 WHERE code = ? AND (single_use = 0 OR uses = 0) AND (expires_at IS NULL OR expires_at > ?)")
+            .bind(code)
+            .bind(now.timestamp())
+            .execute(&mut *tx)
+            .await?;
+        if redeemed.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("INSERT INTO users (username, password_hash) VALUES (?,?)")
+            .bind(username)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn get_channel(&self, name: &str) -> Result<Option<Channel>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT id, name, direct, topic FROM channels WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&mut *conn)
+            .await?;
+        drop(conn);
+        match row {
+            Some(row) => Ok(Some(self.with_members(channel_from_row(&row)).await?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn create_channel(
+        &self,
+        name: &str,
+        created_by: &str,
+        direct: bool,
+        members: &[&str],
+    ) -> Result<Channel, anyhow::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let id = sqlx::query("INSERT INTO channels (name, created_by, direct) VALUES (?, ?, ?)")
+            .bind(name)
+            .bind(created_by)
+            .bind(direct)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        for member in members {
+            sqlx::query("INSERT INTO channel_members (channel_id, username) VALUES (?, ?)")
+                .bind(id)
+                .bind(member)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        self.with_members(Channel::new(id, name.to_string(), direct))
+            .await
+    }
+
+    async fn add_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT OR IGNORE INTO channel_members (channel_id, username) VALUES (?, ?)")
+            .bind(channel_id)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_channel_member(
+        &self,
+        channel_id: i64,
+        username: &str,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("DELETE FROM channel_members WHERE channel_id = ? AND username = ?")
+            .bind(channel_id)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_channel_members(&self, channel_id: i64) -> Result<Vec<String>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query(
+            "SELECT username FROM channel_members WHERE channel_id = ? ORDER BY username",
+        )
+        .bind(channel_id)
+        .fetch_all(&mut *conn)
+        .await?;
+        Ok(rows.iter().map(|row| row.get("username")).collect())
+    }
+
+    async fn set_channel_topic(
+        &self,
+        channel_id: i64,
+        topic: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("UPDATE channels SET topic = ? WHERE id = ?")
+            .bind(topic)
+            .bind(channel_id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_user_channels(&self, username: &str) -> Result<Vec<Channel>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT channels.id, channels.name, channels.direct, channels.topic FROM channels JOIN channel_members ON channel_members.channel_id = channels.id WHERE channel_members.username = ? ORDER BY channels.direct, channels.name")
+            .bind(username)
+            .fetch_all(&mut *conn)
+            .await?;
+        let mut channels: Vec<Channel> = rows.iter().map(channel_from_row).collect();
+        let rows = sqlx::query("SELECT channel_members.channel_id, channel_members.username FROM channel_members JOIN channels ON channels.id = channel_members.channel_id WHERE channels.direct AND channel_members.channel_id IN (SELECT channel_id FROM channel_members WHERE username = ?) ORDER BY channel_members.username")
+            .bind(username)
+            .fetch_all(&mut *conn)
+            .await?;
+        for row in &rows {
+            let channel_id: i64 = row.get("channel_id");
+            if let Some(channel) = channels.iter_mut().find(|channel| channel.id == channel_id) {
+                channel.members.push(row.get("username"));
+            }
+        }
+        Ok(channels)
+    }
+
+    async fn insert_message(&self, message: &Message) -> Result<i64, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let id = sqlx::query(
+            "INSERT INTO messages (content, sender, channel_id, created_at, parent_id) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&message.content)
+        .bind(&message.sender.username)
+        .bind(message.channel_id)
+        .bind(message.created_at.timestamp())
+        .bind(message.parent_id)
+        .execute(&mut *conn)
+        .await?
+        .last_insert_rowid();
+        Ok(id)
+    }
+
+    async fn get_messages(
+        &self,
+        channel_id: i64,
+        username: &str,
+        before_id: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT * FROM ( SELECT messages.id, content, sender, channel_id, messages.created_at, edited_at, deleted, parent_id, (SELECT COUNT(*) FROM messages AS replies WHERE replies.parent_id = messages.id AND replies.deleted = 0) AS reply_count FROM messages JOIN channels ON channels.id = messages.channel_id WHERE channel_id = ?1 AND parent_id IS NULL AND (?3 IS NULL OR messages.id < ?3) AND (channels.direct = 0 OR EXISTS (SELECT 1 FROM channel_members WHERE channel_members.channel_id = channels.id AND channel_members.username = ?2)) ORDER BY messages.id DESC LIMIT ?4) as recent ORDER BY id ASC")
+            .bind(channel_id)
+            .bind(username)
+            .bind(before_id)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn get_messages_around(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+        before: usize,
+        after: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("WITH visible AS (SELECT messages.id, content, sender, channel_id, messages.created_at, edited_at, deleted, parent_id, (SELECT COUNT(*) FROM messages AS replies WHERE replies.parent_id = messages.id AND replies.deleted = 0) AS reply_count FROM messages JOIN channels ON channels.id = messages.channel_id WHERE channel_id = ?1 AND parent_id IS NULL AND (channels.direct = 0 OR EXISTS (SELECT 1 FROM channel_members WHERE channel_members.channel_id = channels.id AND channel_members.username = ?2))) SELECT * FROM (SELECT * FROM (SELECT * FROM visible WHERE id < ?3 ORDER BY id DESC LIMIT ?4) UNION ALL SELECT * FROM (SELECT * FROM visible WHERE id >= ?3 ORDER BY id ASC LIMIT ?5)) ORDER BY id ASC")
+            .bind(channel_id)
+            .bind(username)
+            .bind(message_id)
+            .bind(before as i64)
+            .bind(after as i64 + 1)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn search_messages(
+        &self,
+        search: &MessageSearch,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Message>, anyhow::Error> {
+        // Every word is quoted, so user input can't use FTS5 query syntax.
+        let fts_query = (!search.words.is_empty()).then(|| {
+            search
+                .words
+                .iter()
+                .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT messages.id, messages.content, sender, messages.channel_id, messages.created_at, edited_at, deleted, parent_id, (SELECT COUNT(*) FROM messages AS replies WHERE replies.parent_id = messages.id AND replies.deleted = 0) AS reply_count FROM messages JOIN channel_members ON channel_members.channel_id = messages.channel_id AND channel_members.username = ?1 WHERE messages.deleted = 0 AND (?2 IS NULL OR messages.id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?2)) AND (?3 IS NULL OR sender = ?3) AND (?4 IS NULL OR messages.channel_id = ?4) AND (?5 IS NULL OR messages.created_at >= ?5) AND (?6 IS NULL OR messages.created_at < ?6) ORDER BY messages.id DESC LIMIT ?7")
+            .bind(username)
+            .bind(fts_query)
+            .bind(&search.sender)
+            .bind(search.channel_id)
+            .bind(search.after.map(|time| time.timestamp()))
+            .bind(search.before.map(|time| time.timestamp()))
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn get_message(&self, message_id: i64) -> Result<Option<Message>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let row = sqlx::query("SELECT id, content, sender, channel_id, created_at, edited_at, deleted, parent_id, (SELECT COUNT(*) FROM messages AS replies WHERE replies.parent_id = messages.id AND replies.deleted = 0) AS reply_count FROM messages WHERE id = ?")
+            .bind(message_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+        Ok(row.as_ref().map(message_from_row))
+    }
+
+    async fn get_replies(&self, parent_id: i64) -> Result<Vec<Message>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT id, content, sender, channel_id, created_at, edited_at, deleted, parent_id, 0 AS reply_count FROM messages WHERE parent_id = ? ORDER BY id")
+            .bind(parent_id)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(message_from_row).collect())
+    }
+
+    async fn update_message(
+        &self,
+        message_id: i64,
+        sender: &str,
+        content: &str,
+        edited_at: DateTime<Utc>,
+    ) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let result = sqlx::query("UPDATE messages SET content = ?, edited_at = ? WHERE id = ? AND sender = ? AND deleted = 0")
+            .bind(content)
+            .bind(edited_at.timestamp())
+            .bind(message_id)
+            .bind(sender)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_message(&self, message_id: i64, sender: &str) -> Result<bool, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let result = sqlx::query("UPDATE messages SET content = '', deleted = 1 WHERE id = ? AND sender = ? AND deleted = 0")
+            .bind(message_id)
+            .bind(sender)
+            .execute(&mut *conn)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn toggle_reaction(
+        &self,
+        message_id: i64,
+        username: &str,
+        emoji: &str,
+    ) -> Result<bool, anyhow::Error> {
+        let mut tx = self.db_pool.begin().await?;
+        let removed = sqlx::query(
+            "DELETE FROM reactions WHERE message_id = ? AND username = ? AND emoji = ?",
+        )
+        .bind(message_id)
+        .bind(username)
+        .bind(emoji)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if !removed {
+            sqlx::query("INSERT INTO reactions (message_id, username, emoji) VALUES (?, ?, ?)")
+                .bind(message_id)
+                .bind(username)
+                .bind(emoji)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(!removed)
+    }
+
+    async fn get_reactions(&self, message_ids: &[i64]) -> Result<Vec<Reaction>, anyhow::Error> {
+        if message_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let placeholders = vec!["?"; message_ids.len()].join(", ");
+        let sql = format!(
+            "SELECT message_id, username, emoji FROM reactions WHERE message_id IN ({}) ORDER BY id",
+            placeholders
+        );
+        let mut query = sqlx::query(&sql);
+        for message_id in message_ids {
+            query = query.bind(message_id);
+        }
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = query.fetch_all(&mut *conn).await?;
+        Ok(rows.iter().map(reaction_from_row).collect())
+    }
+
+    async fn mark_read(
+        &self,
+        channel_id: i64,
+        username: &str,
+        message_id: i64,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("UPDATE channel_members SET last_read_id = MAX(last_read_id, ?) WHERE channel_id = ? AND username = ?")
+            .bind(message_id)
+            .bind(channel_id)
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_read_markers(&self, username: &str) -> Result<Vec<ReadMarker>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT channel_members.channel_id, channel_members.last_read_id, COUNT(messages.id) AS unread FROM channel_members LEFT JOIN messages ON messages.channel_id = channel_members.channel_id AND messages.id > channel_members.last_read_id AND messages.deleted = 0 AND messages.parent_id IS NULL AND messages.sender != channel_members.username WHERE channel_members.username = ? GROUP BY channel_members.channel_id, channel_members.last_read_id")
+            .bind(username)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| ReadMarker {
+                channel_id: row.get("channel_id"),
+                last_read_id: row.get("last_read_id"),
+                unread: row.get::<i64, _>("unread") as usize,
+            })
+            .collect())
+    }
+
+    async fn add_mention(
+        &self,
+        message_id: i64,
+        username: &str,
+        seen: bool,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT OR IGNORE INTO mentions (message_id, username, seen) VALUES (?, ?, ?)")
+            .bind(message_id)
+            .bind(username)
+            .bind(seen)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_mentions(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<Mention>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT messages.id, content, sender, channel_id, created_at, edited_at, deleted, parent_id, (SELECT COUNT(*) FROM messages AS replies WHERE replies.parent_id = messages.id AND replies.deleted = 0) AS reply_count, mentions.seen FROM mentions JOIN messages ON messages.id = mentions.message_id WHERE mentions.username = ? AND messages.deleted = 0 ORDER BY messages.id DESC LIMIT ?")
+            .bind(username)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| Mention {
+                message: message_from_row(row),
+                seen: row.get("seen"),
+            })
+            .collect())
+    }
+
+    async fn mark_mentions_seen(&self, username: &str) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("UPDATE mentions SET seen = 1 WHERE username = ? AND seen = 0")
+            .bind(username)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn add_input_history(
+        &self,
+        username: &str,
+        content: &str,
+        keep: usize,
+    ) -> Result<(), anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        sqlx::query("INSERT INTO input_history (username, content) VALUES (?, ?)")
+            .bind(username)
+            .bind(content)
+            .execute(&mut *conn)
+            .await?;
+        sqlx::query("DELETE FROM input_history WHERE username = ?1 AND id <= (SELECT id FROM input_history WHERE username = ?1 ORDER BY id DESC LIMIT 1 OFFSET ?2)")
+            .bind(username)
+            .bind(keep as i64)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_input_history(
+        &self,
+        username: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, anyhow::Error> {
+        let mut conn = self.db_pool.acquire().await?;
+        let rows = sqlx::query("SELECT content FROM (SELECT id, content FROM input_history WHERE username = ? ORDER BY id DESC LIMIT ?) AS recent ORDER BY id ASC")
+            .bind(username)
+            .bind(limit as i64)
+            .fetch_all(&mut *conn)
+            .await?;
+        Ok(rows.iter().map(|row| row.get("content")).collect())
+    }
+}
+
+fn channel_from_row(row: &SqliteRow) -> Channel {
+    Channel {
+        topic: row.get("topic"),
+        ..Channel::new(row.get("id"), row.get("name"), row.get("direct"))
+    }
+}
+
+fn reaction_from_row(row: &SqliteRow) -> Reaction {
+    Reaction {
+        message_id: row.get("message_id"),
+        username: row.get("username"),
+        emoji: row.get("emoji"),
+    }
+}
+
+fn message_from_row(row: &SqliteRow) -> Message {
+    Message {
+        id: row.get::<i64, _>("id"),
+        content: row.get::<String, _>("content"),
+        sender: User::authenticated(row.get::<String, _>("sender").as_str()),
+        channel_id: row.get::<i64, _>("channel_id"),
+        created_at: timestamp(row.get::<i64, _>("created_at")),
+        edited_at: row.get::<Option<i64>, _>("edited_at").map(timestamp),
+        deleted: row.get::<bool, _>("deleted"),
+        parent_id: row.get::<Option<i64>, _>("parent_id"),
+        reply_count: row.get::<i64, _>("reply_count") as usize,
+    }
+}
@@ -0,0 +1,174 @@
+use log::info;
+
+use sqlx::Row;
+use sqlx::sqlite::SqlitePool;
+
+use chrono::Utc;
+
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+/// Schema history of `sshlack.db`. Migrations are applied in order and must
+/// never be edited once released; add a new one instead.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "messages and users",
+        statements: &[
+            "CREATE TABLE IF NOT EXISTS messages (id INTEGER PRIMARY KEY AUTOINCREMENT, content TEXT, sender TEXT)",
+            "CREATE TABLE IF NOT EXISTS users (id INTEGER PRIMARY KEY AUTOINCREMENT, username TEXT UNIQUE, password_hash TEXT)",
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "channels",
+        statements: &[
+            "CREATE TABLE channels (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT UNIQUE, created_by TEXT)",
+            "CREATE TABLE channel_members (channel_id INTEGER REFERENCES channels(id), username TEXT, PRIMARY KEY (channel_id, username))",
+            "ALTER TABLE messages ADD COLUMN channel_id INTEGER REFERENCES channels(id)",
+            "INSERT INTO channels (name, created_by) VALUES ('general', '*')",
+            "UPDATE messages SET channel_id = (SELECT id FROM channels WHERE name = 'general')",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "direct conversations",
+        statements: &["ALTER TABLE channels ADD COLUMN direct INTEGER NOT NULL DEFAULT 0"],
+    },
+    Migration {
+        version: 4,
+        description: "user keys",
+        statements: &[
+            "CREATE TABLE user_keys (id INTEGER PRIMARY KEY AUTOINCREMENT, user_id INTEGER NOT NULL REFERENCES users(id), public_key TEXT UNIQUE, comment TEXT)",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "message timestamps, edits and deletion",
+        statements: &[
+            "ALTER TABLE messages ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0",
+            "ALTER TABLE messages ADD COLUMN edited_at INTEGER",
+            "ALTER TABLE messages ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "invites",
+        statements: &[
+            "CREATE TABLE invites (code TEXT PRIMARY KEY, created_by TEXT, created_at INTEGER NOT NULL, expires_at INTEGER, single_use INTEGER NOT NULL, uses INTEGER NOT NULL DEFAULT 0)",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "full-text message search",
+        statements: &[
+            "CREATE VIRTUAL TABLE messages_fts USING fts5(content, content='messages', content_rowid='id')",
+            "CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content); END",
+            "CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content); END",
+            "CREATE TRIGGER messages_fts_update AFTER UPDATE OF content ON messages BEGIN INSERT INTO messages_fts (messages_fts, rowid, content) VALUES ('delete', old.id, old.content); INSERT INTO messages_fts (rowid, content) VALUES (new.id, new.content); END",
+            "INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "input history",
+        statements: &[
+            "CREATE TABLE input_history (id INTEGER PRIMARY KEY AUTOINCREMENT, username TEXT NOT NULL, content TEXT NOT NULL)",
+            "CREATE INDEX input_history_username ON input_history (username, id)",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "nicknames and channel topics",
+        statements: &[
+            "ALTER TABLE users ADD COLUMN nickname TEXT",
+            "ALTER TABLE channels ADD COLUMN topic TEXT",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "mentions",
+        statements: &[
+            "CREATE TABLE mentions (message_id INTEGER NOT NULL REFERENCES messages(id), username TEXT NOT NULL, seen INTEGER NOT NULL DEFAULT 0, PRIMARY KEY (message_id, username))",
+            "CREATE INDEX mentions_username ON mentions (username, message_id)",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "read markers",
+        statements: &[
+            "ALTER TABLE channel_members ADD COLUMN last_read_id INTEGER NOT NULL DEFAULT 0",
+            // Existing members have seen everything so far.
+            "UPDATE channel_members SET last_read_id = COALESCE((SELECT MAX(id) FROM messages WHERE messages.channel_id = channel_members.channel_id), 0)",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "status text",
+        statements: &["ALTER TABLE users ADD COLUMN status TEXT"],
+    },
+    Migration {
+        version: 13,
+        description: "reactions",
+        statements: &[
+            "CREATE TABLE reactions (id INTEGER PRIMARY KEY AUTOINCREMENT, message_id INTEGER NOT NULL REFERENCES messages(id), username TEXT NOT NULL, emoji TEXT NOT NULL, UNIQUE (message_id, username, emoji))",
+        ],
+    },
+    Migration {
+        version: 14,
+        description: "threads",
+        statements: &[
+            "ALTER TABLE messages ADD COLUMN parent_id INTEGER REFERENCES messages(id)",
+            "CREATE INDEX messages_parent_id ON messages (parent_id)",
+        ],
+    },
+    Migration {
+        version: 15,
+        description: "direct conversation names",
+        statements: &["UPDATE channels SET name = '@' || name WHERE direct"],
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+pub async fn current_version(db_pool: &SqlitePool) -> Result<i64, anyhow::Error> {
+    sqlx::query("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, description TEXT, applied_at INTEGER)")
+        .execute(db_pool)
+        .await?;
+    let row = sqlx::query("SELECT COALESCE(MAX(version), 0) AS version FROM schema_version")
+        .fetch_one(db_pool)
+        .await?;
+    Ok(row.get("version"))
+}
+
+/// Applies every migration newer than the database's schema version. Each
+/// migration runs in its own transaction together with its version record.
+pub async fn migrate(db_pool: &SqlitePool) -> Result<(), anyhow::Error> {
+    let current = current_version(db_pool).await?;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!(
+            "Applying migration {}: {}",
+            migration.version, migration.description
+        );
+        let mut tx = db_pool.begin().await?;
+        for statement in migration.statements {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+        sqlx::query(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?, ?, ?)",
+        )
+        .bind(migration.version)
+        .bind(migration.description)
+        .bind(Utc::now().timestamp())
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+    }
+    Ok(())
+}
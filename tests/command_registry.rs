@@ -0,0 +1,66 @@
+use sshlack::app::{
+    AppController,
+    app_commands::builtin_commands,
+    command_registry::{Command, CommandArgs, CommandFuture, CommandRegistry},
+};
+
+fn noop<'a>(_controller: &'a mut AppController, _args: CommandArgs<'a>) -> CommandFuture<'a> {
+    Box::pin(async { Ok(()) })
+}
+
+fn registry() -> CommandRegistry {
+    let mut registry = CommandRegistry::default();
+    for (name, description) in [("/topic", "Set the topic"), ("/me", "Send an action")] {
+        registry.register(Command {
+            name,
+            usage: "<text>",
+            description,
+            handler: noop,
+        });
+    }
+    registry
+}
+
+#[test]
+fn parses_commands_and_arguments() {
+    let registry = registry();
+    let (command, args) = registry.parse("  /TOPIC  release  day \n").unwrap();
+    assert_eq!(command.name, "/topic");
+    assert_eq!(args.text, "release  day");
+    assert_eq!(args.words, ["release", "day"]);
+    assert_eq!(args.usage_error().to_string(), "Usage: /topic <text>");
+
+    let (command, args) = registry.parse("/me").unwrap();
+    assert_eq!(command.name, "/me");
+    assert!(args.text.is_empty() && args.words.is_empty());
+}
+
+#[test]
+fn rejects_unknown_commands() {
+    let registry = registry();
+    let error = registry.parse("/shrug ok").err().unwrap();
+    assert_eq!(error.to_string(), "Unknown command /shrug, see /help");
+    assert!(registry.parse("/").is_err());
+}
+
+#[test]
+fn registering_replaces_and_sorts() {
+    let mut registry = registry();
+    registry.register(Command {
+        name: "/me",
+        usage: "<action>",
+        description: "Replaced",
+        handler: noop,
+    });
+    let names: Vec<&str> = registry.commands().iter().map(|c| c.name).collect();
+    assert_eq!(names, ["/me", "/topic"]);
+    assert_eq!(registry.get("/me").unwrap().description, "Replaced");
+}
+
+#[test]
+fn collects_commands_from_feature_modules() {
+    let registry = builtin_commands();
+    for name in ["/join", "/mentions", "/away", "/react"] {
+        assert!(registry.get(name).is_some(), "{}", name);
+    }
+}
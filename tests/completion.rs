@@ -0,0 +1,35 @@
+use sshlack::app::completion::{COMPLETION_LIMIT, emoji_candidates, parse_emoji, word_start};
+
+#[test]
+fn finds_the_word_before_the_cursor() {
+    assert_eq!(word_start("hi @al", 6), 3);
+    assert_eq!(word_start("hi @al", 2), 0);
+    assert_eq!(word_start("line\n:roc", 9), 5);
+    assert_eq!(word_start("", 0), 0);
+}
+
+#[test]
+fn completes_emoji_shortcodes() {
+    let rocket = emoji_candidates("rock");
+    assert!(
+        rocket
+            .iter()
+            .any(|candidate| candidate.replacement == "🚀" && candidate.label == "🚀 :rocket:")
+    );
+    assert!(
+        emoji_candidates("thumbs_u")
+            .iter()
+            .any(|candidate| candidate.replacement == "👍")
+    );
+    assert_eq!(emoji_candidates("s").len(), COMPLETION_LIMIT);
+    assert!(emoji_candidates("no_such_emoji").is_empty());
+}
+
+#[test]
+fn parses_reaction_emoji() {
+    assert_eq!(parse_emoji(":tada:"), Some("🎉"));
+    assert_eq!(parse_emoji("rocket"), Some("🚀"));
+    assert_eq!(parse_emoji("👍"), Some("👍"));
+    assert_eq!(parse_emoji(":no_such_emoji:"), None);
+    assert_eq!(parse_emoji("text"), None);
+}
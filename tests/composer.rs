@@ -0,0 +1,64 @@
+use sshlack::app::composer::Composer;
+
+fn composer(text: &str) -> Composer {
+    let mut composer = Composer::default();
+    composer.set_text(text.to_string());
+    composer
+}
+
+#[test]
+fn moves_and_edits_at_cursor() {
+    let mut composer = composer("héllo");
+    composer.move_left();
+    composer.move_left();
+    composer.insert('X');
+    assert_eq!(composer.text(), "hélXlo");
+    composer.move_home();
+    composer.move_left();
+    composer.delete();
+    assert_eq!(composer.text(), "élXlo");
+    composer.move_right();
+    composer.backspace();
+    assert_eq!(composer.text(), "lXlo");
+    assert_eq!(composer.cursor(), 0);
+    composer.move_end();
+    composer.move_right();
+    assert_eq!(composer.cursor(), composer.text().len());
+}
+
+#[test]
+fn home_and_end_stay_on_the_current_line() {
+    let mut composer = composer("first\nsecond");
+    composer.move_home();
+    assert_eq!(composer.cursor(), "first\n".len());
+    composer.move_left();
+    composer.move_home();
+    assert_eq!(composer.cursor(), 0);
+    composer.move_end();
+    assert_eq!(composer.cursor(), "first".len());
+}
+
+#[test]
+fn deletes_words_and_lines() {
+    let mut composer = composer("one two  three  ");
+    composer.delete_word();
+    assert_eq!(composer.text(), "one two  ");
+    composer.delete_word();
+    assert_eq!(composer.text(), "one ");
+
+    let mut composer = self::composer("keep\ndrop this");
+    composer.delete_to_line_start();
+    assert_eq!(composer.text(), "keep\n");
+    composer.delete_word();
+    assert_eq!(composer.text(), "");
+}
+
+#[test]
+fn replaces_text_before_cursor() {
+    let mut composer = composer("ab:cd");
+    composer.move_left();
+    composer.move_left();
+    composer.replace_before_cursor(|before| before.to_uppercase());
+    assert_eq!(composer.text(), "AB:cd");
+    assert_eq!(composer.cursor(), 3);
+}
@@ -0,0 +1,47 @@
+use sshlack::app::highlight::highlight;
+
+fn lines(code: &[&str]) -> Vec<String> {
+    code.iter().map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn highlights_known_languages() {
+    let code = lines(&["fn main() {", "    let answer = 42;", "}"]);
+    let highlighted = highlight(&code, "rust").unwrap();
+    assert_eq!(highlighted.len(), 3);
+    for (spans, line) in highlighted.iter().zip(&code) {
+        let text: String = spans.iter().map(|span| span.content.as_ref()).collect();
+        assert_eq!(&text, line);
+    }
+    let keyword = highlighted[0]
+        .iter()
+        .find(|span| span.content == "fn")
+        .unwrap();
+    let number = highlighted[1]
+        .iter()
+        .find(|span| span.content == "42")
+        .unwrap();
+    assert!(keyword.style.fg.is_some());
+    assert_ne!(keyword.style.fg, number.style.fg);
+
+    for language in ["sh", "yaml", "yml", "Python"] {
+        assert!(
+            highlight(&lines(&["x"]), language).is_some(),
+            "{}",
+            language
+        );
+    }
+}
+
+#[test]
+fn leaves_unknown_languages_plain() {
+    assert!(highlight(&lines(&["hello"]), "not-a-language").is_none());
+}
+
+#[test]
+fn leaves_long_code_plain() {
+    let many_lines = vec!["let x = 1;".to_string(); 1000];
+    assert!(highlight(&many_lines, "rust").is_none());
+    let long_line = vec![format!("let x = \"{}\";", "a".repeat(100_000))];
+    assert!(highlight(&long_line, "rust").is_none());
+}
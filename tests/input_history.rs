@@ -0,0 +1,62 @@
+use sshlack::app::input_history::InputHistory;
+
+fn history() -> InputHistory {
+    InputHistory::new(vec![
+        "hello".to_string(),
+        "/join #ops".to_string(),
+        "Hello again".to_string(),
+    ])
+}
+
+#[test]
+fn browses_back_to_the_draft() {
+    let mut history = history();
+    assert_eq!(history.older("draft"), Some("Hello again"));
+    assert_eq!(history.older("ignored"), Some("/join #ops"));
+    assert_eq!(history.older("ignored"), Some("hello"));
+    assert_eq!(history.older("ignored"), Some("hello"));
+    assert_eq!(history.newer().as_deref(), Some("/join #ops"));
+    assert_eq!(history.newer().as_deref(), Some("Hello again"));
+    assert_eq!(history.newer().as_deref(), Some("draft"));
+    assert_eq!(history.newer(), None);
+}
+
+#[test]
+fn skips_repeated_inputs() {
+    let mut history = history();
+    assert!(!history.push("Hello again"));
+    assert!(history.push("hello"));
+    assert_eq!(history.older(""), Some("hello"));
+    assert_eq!(history.older(""), Some("Hello again"));
+}
+
+#[test]
+fn searches_backwards_ignoring_case() {
+    let mut history = history();
+    history.start_search("draft");
+    assert_eq!(
+        history.set_search_query("h".to_string()),
+        Some("Hello again")
+    );
+    assert_eq!(
+        history.set_search_query("hel".to_string()),
+        Some("Hello again")
+    );
+    assert_eq!(history.search_older(), Some("hello"));
+    // No older match keeps the current one.
+    assert_eq!(history.search_older(), Some("hello"));
+    assert_eq!(history.set_search_query("helx".to_string()), None);
+    assert!(history.search().unwrap().found.is_none());
+    assert_eq!(history.cancel_search(), "draft");
+    assert!(history.search().is_none());
+}
+
+#[test]
+fn browses_on_from_an_accepted_match() {
+    let mut history = history();
+    history.start_search("");
+    history.set_search_query("join".to_string());
+    history.accept_search();
+    assert_eq!(history.older(""), Some("hello"));
+    assert_eq!(history.newer().as_deref(), Some("/join #ops"));
+}
@@ -0,0 +1,91 @@
+use sshlack::app::markdown::{Block, Fragment, parse, parse_inline};
+
+/// Fragments as `(text, bold, italic, code)`.
+fn formatted(text: &str) -> Vec<(&str, bool, bool, bool)> {
+    parse_inline(text)
+        .iter()
+        .map(|fragment| (fragment.text, fragment.bold, fragment.italic, fragment.code))
+        .collect()
+}
+
+#[test]
+fn formats_bold_italic_and_code() {
+    assert_eq!(
+        formatted("a *bold* and _italic_ `x * y`"),
+        [
+            ("a ", false, false, false),
+            ("bold", true, false, false),
+            (" and ", false, false, false),
+            ("italic", false, true, false),
+            (" ", false, false, false),
+            ("x * y", false, false, true),
+        ]
+    );
+    assert_eq!(
+        formatted("**both _at once_**"),
+        [
+            ("both ", true, false, false),
+            ("at once", true, true, false)
+        ]
+    );
+    assert_eq!(
+        formatted("run ```ls -la``` now"),
+        [
+            ("run ", false, false, false),
+            ("ls -la", false, false, true),
+            (" now", false, false, false),
+        ]
+    );
+}
+
+#[test]
+fn leaves_delimiters_inside_words() {
+    for text in [
+        "snake_case_name",
+        "2*3*4",
+        "a * b * c",
+        "*unclosed",
+        "`unclosed",
+    ] {
+        assert_eq!(formatted(text), [(text, false, false, false)]);
+    }
+}
+
+#[test]
+fn leaves_unclosed_delimiters_plain() {
+    let text = "*a _b **c __d ".repeat(20_000);
+    let started = std::time::Instant::now();
+    assert_eq!(formatted(&text), [(text.as_str(), false, false, false)]);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+}
+
+#[test]
+fn parses_quotes_and_code_blocks() {
+    let plain = |text| {
+        vec![Fragment {
+            text,
+            bold: false,
+            italic: false,
+            code: false,
+        }]
+    };
+    assert_eq!(
+        parse("look:\n```rust\nfn main() {\n    let _x = 1;\n}\n```\n> quoted\nafter"),
+        [
+            Block::Text(plain("look:")),
+            Block::Code {
+                language: Some("rust"),
+                lines: vec!["fn main() {", "    let _x = 1;", "}"],
+            },
+            Block::Quote(plain("quoted")),
+            Block::Text(plain("after")),
+        ]
+    );
+    assert_eq!(
+        parse("```\n*not bold*"),
+        [Block::Code {
+            language: None,
+            lines: vec!["*not bold*"],
+        }]
+    );
+}
@@ -0,0 +1,45 @@
+use sshlack::app::AppState;
+use sshlack::db_models::{Channel, Message, User, mention_ranges};
+
+fn mentions(content: &str) -> Vec<String> {
+    Message::new(content.to_string(), User::authenticated("alice"), 1)
+        .mentions()
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn finds_mentions_at_word_starts() {
+    assert_eq!(
+        mentions("@bob and @carol-2, thanks @dave."),
+        ["bob", "carol-2", "dave"]
+    );
+    assert_eq!(mentions("(@bob) @bob @bob"), ["bob"]);
+    assert!(mentions("mail bob@example.com or @ me, @@bob").is_empty());
+    assert_eq!(mentions("@j.doe: hi @émile"), ["j.doe", "émile"]);
+}
+
+#[test]
+fn ranges_include_the_at_sign() {
+    let text = "hi @bob!";
+    let ranges = mention_ranges(text);
+    assert_eq!(ranges, [(3, 7)]);
+    assert_eq!(&text[ranges[0].0..ranges[0].1], "@bob");
+}
+
+#[test]
+fn alerts_members_only() {
+    let mut state = AppState::new(User::authenticated("bob"));
+    state
+        .channels
+        .push(Channel::new(1, "general".to_string(), false));
+    let message = |content: &str, sender: &str, channel_id| {
+        Message::new(content.to_string(), User::authenticated(sender), channel_id)
+    };
+    assert!(state.alerts_viewer(&message("hi @bob", "alice", 1)));
+    assert!(!state.alerts_viewer(&message("hi @alice", "alice", 1)));
+    assert!(!state.alerts_viewer(&message("note to @bob", "bob", 1)));
+    // A direct conversation between others that mentions bob.
+    assert!(!state.alerts_viewer(&message("don't tell @bob", "alice", 2)));
+}
@@ -0,0 +1,96 @@
+use sqlx::Row;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+use sshlack::storage::sqlite_migrations::{current_version, latest_version, migrate};
+
+async fn memory_pool() -> SqlitePool {
+    // A single connection, as every connection to `:memory:` opens a new database.
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+async fn columns(pool: &SqlitePool, table: &str) -> Vec<String> {
+    sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(pool)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get::<String, _>("name"))
+        .collect()
+}
+
+#[tokio::test]
+async fn migrates_empty_database() {
+    let pool = memory_pool().await;
+    migrate(&pool).await.unwrap();
+
+    assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+    assert!(
+        columns(&pool, "messages")
+            .await
+            .contains(&"channel_id".to_string())
+    );
+    assert!(
+        columns(&pool, "user_keys")
+            .await
+            .contains(&"public_key".to_string())
+    );
+}
+
+#[tokio::test]
+async fn upgrades_original_two_table_layout() {
+    let pool = memory_pool().await;
+    sqlx::query(
+        "CREATE TABLE messages (id INTEGER PRIMARY KEY AUTOINCREMENT, content TEXT, sender TEXT)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    sqlx::query("CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, username TEXT UNIQUE, password_hash TEXT)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO users (username, password_hash) VALUES ('alice', 'hash')")
+        .execute(&pool)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO messages (content, sender) VALUES ('hello', 'alice'), ('hi', 'bob')")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    migrate(&pool).await.unwrap();
+
+    assert_eq!(current_version(&pool).await.unwrap(), latest_version());
+
+    let rows = sqlx::query("SELECT content, channels.name AS channel, deleted FROM messages JOIN channels ON channels.id = messages.channel_id ORDER BY messages.id")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0].get::<String, _>("content"), "hello");
+    assert_eq!(rows[1].get::<String, _>("channel"), "general");
+    assert!(!rows[1].get::<bool, _>("deleted"));
+
+    let user = sqlx::query("SELECT password_hash FROM users WHERE username = 'alice'")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(user.get::<String, _>("password_hash"), "hash");
+}
+
+#[tokio::test]
+async fn migrating_twice_is_a_no_op() {
+    let pool = memory_pool().await;
+    migrate(&pool).await.unwrap();
+    migrate(&pool).await.unwrap();
+
+    let applied = sqlx::query("SELECT COUNT(*) AS count FROM schema_version")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(applied.get::<i64, _>("count"), latest_version());
+}
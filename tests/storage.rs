@@ -0,0 +1,633 @@
+use sqlx::sqlite::SqlitePoolOptions;
+
+use chrono::{Duration, Utc};
+
+use sshlack::db_models::{Channel, Invite, Message, MessageSearch, User};
+use sshlack::storage::{MemoryStorage, SqliteStorage, Storage};
+
+async fn sqlite_storage() -> SqliteStorage {
+    // A single connection, as every connection to `:memory:` opens a new database.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    let storage = SqliteStorage::from_pool(pool);
+    storage.migrate().await.unwrap();
+    storage
+}
+
+/// Exercises every storage method. Expects an empty, migrated database.
+async fn check_storage(storage: &dyn Storage) {
+    // Users
+    assert!(!storage.user_exists("alice").await.unwrap());
+    assert_eq!(storage.get_password_hash("alice").await.unwrap(), None);
+    assert!(storage.create_first_user("alice", "hash-a").await.unwrap());
+    assert!(
+        !storage
+            .create_first_user("mallory", "hash-m")
+            .await
+            .unwrap()
+    );
+    assert!(!storage.user_exists("mallory").await.unwrap());
+    storage.create_user("bob", "hash-b").await.unwrap();
+    assert!(storage.user_exists("alice").await.unwrap());
+    assert_eq!(
+        storage.get_password_hash("alice").await.unwrap().as_deref(),
+        Some("hash-a")
+    );
+    assert!(storage.create_user("alice", "other").await.is_err());
+    storage.create_user("alina", "hash-c").await.unwrap();
+    assert_eq!(
+        storage.find_usernames("al", 10).await.unwrap(),
+        ["alice", "alina"]
+    );
+    assert_eq!(storage.find_usernames("al", 1).await.unwrap(), ["alice"]);
+    assert_eq!(
+        storage.find_usernames("", 10).await.unwrap(),
+        ["alice", "alina", "bob"]
+    );
+    assert!(storage.find_usernames("%", 10).await.unwrap().is_empty());
+
+    // Nicknames
+    assert!(storage.get_nicknames().await.unwrap().is_empty());
+    storage.set_nickname("alice", Some("Al")).await.unwrap();
+    assert_eq!(
+        storage.get_nicknames().await.unwrap(),
+        [("alice".to_string(), "Al".to_string())]
+    );
+    storage.set_nickname("alice", None).await.unwrap();
+    assert!(storage.get_nicknames().await.unwrap().is_empty());
+
+    // Status text
+    assert_eq!(storage.get_status_text("alice").await.unwrap(), None);
+    storage
+        .set_status_text("alice", Some("on holiday"))
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_status_text("alice").await.unwrap().as_deref(),
+        Some("on holiday")
+    );
+    storage.set_status_text("alice", None).await.unwrap();
+    assert_eq!(storage.get_status_text("alice").await.unwrap(), None);
+    assert_eq!(storage.get_status_text("nobody").await.unwrap(), None);
+
+    // Keys
+    storage
+        .add_user_key("alice", "ssh-ed25519 AAAA", "laptop")
+        .await
+        .unwrap();
+    assert!(storage.key_exists("ssh-ed25519 AAAA").await.unwrap());
+    assert!(
+        storage
+            .user_has_key("alice", "ssh-ed25519 AAAA")
+            .await
+            .unwrap()
+    );
+    assert!(
+        !storage
+            .user_has_key("bob", "ssh-ed25519 AAAA")
+            .await
+            .unwrap()
+    );
+    let keys = storage.get_user_keys("alice").await.unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].comment, "laptop");
+    assert!(!storage.delete_user_key("bob", keys[0].id).await.unwrap());
+    assert!(storage.delete_user_key("alice", keys[0].id).await.unwrap());
+    assert!(storage.get_user_keys("alice").await.unwrap().is_empty());
+
+    // Invites
+    let admin = User::authenticated("alice");
+    let single = Invite::generate(&admin, None);
+    let expiring = Invite::generate(&admin, Some(Utc::now() + Duration::hours(1)));
+    let expired = Invite::generate(&admin, Some(Utc::now() - Duration::hours(1)));
+    for invite in [&single, &expiring, &expired] {
+        storage.create_invite(invite).await.unwrap();
+    }
+    assert_eq!(storage.get_invites().await.unwrap().len(), 3);
+    let signup = |username, code, now| storage.create_user_with_invite(username, "hash", code, now);
+    assert!(signup("ivan", &single.code, Utc::now()).await.unwrap());
+    assert!(!signup("judy", &single.code, Utc::now()).await.unwrap());
+    assert!(signup("judy", &expiring.code, Utc::now()).await.unwrap());
+    assert!(signup("kim", &expiring.code, Utc::now()).await.unwrap());
+    assert!(
+        !signup("lee", &expiring.code, Utc::now() + Duration::hours(2))
+            .await
+            .unwrap()
+    );
+    assert!(!signup("lee", &expired.code, Utc::now()).await.unwrap());
+    assert!(!signup("lee", "missing", Utc::now()).await.unwrap());
+    assert!(!storage.user_exists("lee").await.unwrap());
+    let reusable = Invite::generate(&admin, Some(Utc::now() + Duration::hours(1)));
+    storage.create_invite(&reusable).await.unwrap();
+    assert!(
+        storage
+            .create_user_with_invite("dave", "hash-d", &reusable.code, Utc::now())
+            .await
+            .unwrap()
+    );
+    assert!(
+        storage
+            .create_user_with_invite("dave", "hash-d", &reusable.code, Utc::now())
+            .await
+            .is_err()
+    );
+    assert!(
+        !storage
+            .create_user_with_invite("erin", "hash-e", "missing", Utc::now())
+            .await
+            .unwrap()
+    );
+    assert!(!storage.user_exists("erin").await.unwrap());
+    let invites = storage.get_invites().await.unwrap();
+    let reusable = invites.iter().find(|i| i.code == reusable.code).unwrap();
+    assert_eq!(reusable.uses, 1);
+    assert!(storage.delete_invite(&expiring.code).await.unwrap());
+    assert!(!storage.delete_invite(&expiring.code).await.unwrap());
+    let invites = storage.get_invites().await.unwrap();
+    let single = invites.iter().find(|i| i.code == single.code).unwrap();
+    assert_eq!(single.uses, 1);
+    assert!(!single.is_valid(Utc::now()));
+
+    // Channels
+    let random = storage
+        .create_channel("random", "alice", false, &[])
+        .await
+        .unwrap();
+    assert!(
+        storage
+            .create_channel("random", "bob", false, &[])
+            .await
+            .is_err()
+    );
+    let direct_name = Channel::direct_name(&["bob".to_string(), "alice".to_string()]);
+    let direct = storage
+        .create_channel(&direct_name, "alice", true, &["alice", "bob"])
+        .await
+        .unwrap();
+    assert!(direct.direct);
+    assert_eq!(direct.members, ["alice", "bob"]);
+    // Direct conversations don't share names with channels, even when a
+    // username looks like a channel name or contains a comma.
+    storage
+        .create_channel("frank", "frank", false, &[])
+        .await
+        .unwrap();
+    storage
+        .create_channel(
+            &Channel::direct_name(&["frank".to_string()]),
+            "frank",
+            true,
+            &["frank"],
+        )
+        .await
+        .unwrap();
+    assert_ne!(
+        Channel::direct_name(&["a,b".to_string(), "c".to_string()]),
+        Channel::direct_name(&["a".to_string(), "b,c".to_string()])
+    );
+    storage
+        .add_channel_member(random.id, "alice")
+        .await
+        .unwrap();
+    storage
+        .add_channel_member(random.id, "alice")
+        .await
+        .unwrap();
+    let channels = storage.get_user_channels("alice").await.unwrap();
+    let names: Vec<&str> = channels.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["random", "@alice,bob"]);
+    assert!(channels[0].members.is_empty());
+    assert_eq!(channels[1].members, ["alice", "bob"]);
+    assert_eq!(
+        storage
+            .get_channel(&direct_name)
+            .await
+            .unwrap()
+            .unwrap()
+            .members,
+        ["alice", "bob"]
+    );
+    assert_eq!(
+        storage.get_channel("random").await.unwrap().unwrap().id,
+        random.id
+    );
+    assert_eq!(
+        storage.get_channel_members(direct.id).await.unwrap(),
+        ["alice", "bob"]
+    );
+    assert_eq!(
+        storage.get_channel("random").await.unwrap().unwrap().topic,
+        None
+    );
+    storage
+        .set_channel_topic(random.id, Some("anything goes"))
+        .await
+        .unwrap();
+    assert_eq!(
+        storage
+            .get_channel("random")
+            .await
+            .unwrap()
+            .unwrap()
+            .topic
+            .as_deref(),
+        Some("anything goes")
+    );
+    assert_eq!(
+        storage.get_user_channels("alice").await.unwrap()[0]
+            .topic
+            .as_deref(),
+        Some("anything goes")
+    );
+    storage.set_channel_topic(random.id, None).await.unwrap();
+    assert_eq!(
+        storage.get_channel("random").await.unwrap().unwrap().topic,
+        None
+    );
+    storage
+        .remove_channel_member(random.id, "alice")
+        .await
+        .unwrap();
+    assert_eq!(storage.get_user_channels("alice").await.unwrap().len(), 1);
+
+    // Messages
+    let alice = User::authenticated("alice");
+    let mut ids = Vec::new();
+    for content in ["one", "two", "three"] {
+        let message = Message::new(content.to_string(), alice.clone(), random.id);
+        ids.push(storage.insert_message(&message).await.unwrap());
+    }
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    let recent = storage
+        .get_messages(random.id, "bob", None, 2)
+        .await
+        .unwrap();
+    let contents: Vec<&str> = recent.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, ["two", "three"]);
+    let older = storage
+        .get_messages(random.id, "bob", Some(ids[2]), 1)
+        .await
+        .unwrap();
+    let older: Vec<&str> = older.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(older, ["two"]);
+    assert!(
+        storage
+            .get_messages(random.id, "bob", Some(ids[0]), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let secret = Message::new("secret".to_string(), alice.clone(), direct.id);
+    let secret_id = storage.insert_message(&secret).await.unwrap();
+    assert_eq!(
+        storage
+            .get_messages(direct.id, "bob", None, 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(
+        storage
+            .get_messages(direct.id, "carol", Some(i64::MAX), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    assert!(
+        !storage
+            .update_message(ids[0], "bob", "hijacked", Utc::now())
+            .await
+            .unwrap()
+    );
+    assert!(
+        storage
+            .update_message(ids[0], "alice", "uno", Utc::now())
+            .await
+            .unwrap()
+    );
+    let edited = storage.get_message(ids[0]).await.unwrap().unwrap();
+    assert_eq!(edited.content, "uno");
+    assert!(edited.edited_at.is_some());
+
+    assert!(storage.delete_message(ids[1], "alice").await.unwrap());
+    assert!(!storage.delete_message(ids[1], "alice").await.unwrap());
+    let deleted = storage.get_message(ids[1]).await.unwrap().unwrap();
+    assert!(deleted.deleted);
+    assert!(deleted.content.is_empty());
+    assert!(storage.get_message(i64::MAX).await.unwrap().is_none());
+
+    let around = storage
+        .get_messages_around(random.id, "bob", ids[1], 1, 0)
+        .await
+        .unwrap();
+    let around: Vec<i64> = around.iter().map(|m| m.id).collect();
+    assert_eq!(around, [ids[0], ids[1]]);
+    assert!(
+        storage
+            .get_messages_around(direct.id, "carol", i64::MAX, 10, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // Mentions
+    storage.add_mention(ids[0], "bob", true).await.unwrap();
+    storage.add_mention(ids[1], "bob", false).await.unwrap();
+    storage.add_mention(ids[2], "bob", false).await.unwrap();
+    storage.add_mention(ids[2], "bob", false).await.unwrap();
+    storage.add_mention(ids[2], "carol", false).await.unwrap();
+    let mentions = storage.get_mentions("bob", 10).await.unwrap();
+    let mentioned: Vec<(i64, bool)> = mentions.iter().map(|m| (m.message.id, m.seen)).collect();
+    assert_eq!(mentioned, [(ids[2], false), (ids[0], true)]);
+    assert_eq!(storage.get_mentions("bob", 1).await.unwrap().len(), 1);
+    storage.mark_mentions_seen("bob").await.unwrap();
+    assert!(
+        storage
+            .get_mentions("bob", 10)
+            .await
+            .unwrap()
+            .iter()
+            .all(|m| m.seen)
+    );
+    assert!(!storage.get_mentions("carol", 10).await.unwrap()[0].seen);
+
+    // Reactions
+    for (message_id, username, emoji) in [
+        (ids[0], "bob", "👍"),
+        (ids[2], "alice", "🎉"),
+        (ids[0], "alice", "👍"),
+    ] {
+        assert!(
+            storage
+                .toggle_reaction(message_id, username, emoji)
+                .await
+                .unwrap()
+        );
+    }
+    let reactions = storage.get_reactions(&[ids[0], ids[2]]).await.unwrap();
+    let reactions: Vec<(i64, &str, &str)> = reactions
+        .iter()
+        .map(|r| (r.message_id, r.username.as_str(), r.emoji.as_str()))
+        .collect();
+    assert_eq!(
+        reactions,
+        [
+            (ids[0], "bob", "👍"),
+            (ids[2], "alice", "🎉"),
+            (ids[0], "alice", "👍")
+        ]
+    );
+    assert!(!storage.toggle_reaction(ids[0], "bob", "👍").await.unwrap());
+    assert_eq!(storage.get_reactions(&[ids[0]]).await.unwrap().len(), 1);
+    assert!(storage.get_reactions(&[]).await.unwrap().is_empty());
+
+    // Threads
+    let mut reply_ids = Vec::new();
+    for content in ["first reply", "second reply"] {
+        let mut reply = Message::new(content.to_string(), alice.clone(), random.id);
+        reply.parent_id = Some(ids[2]);
+        reply_ids.push(storage.insert_message(&reply).await.unwrap());
+    }
+    let replies = storage.get_replies(ids[2]).await.unwrap();
+    let replies: Vec<(i64, Option<i64>)> = replies.iter().map(|m| (m.id, m.parent_id)).collect();
+    assert_eq!(
+        replies,
+        [(reply_ids[0], Some(ids[2])), (reply_ids[1], Some(ids[2]))]
+    );
+    let recent = storage
+        .get_messages(random.id, "bob", None, 10)
+        .await
+        .unwrap();
+    let recent: Vec<(i64, usize)> = recent.iter().map(|m| (m.id, m.reply_count)).collect();
+    assert_eq!(recent, [(ids[0], 0), (ids[1], 0), (ids[2], 2)]);
+    assert!(storage.delete_message(reply_ids[0], "alice").await.unwrap());
+    let parent = storage.get_message(ids[2]).await.unwrap().unwrap();
+    assert_eq!(parent.reply_count, 1);
+    assert_eq!(storage.get_replies(ids[2]).await.unwrap().len(), 2);
+
+    // Read markers
+    let markers = |username| async move {
+        storage
+            .get_read_markers(username)
+            .await
+            .unwrap()
+            .iter()
+            .map(|marker| (marker.channel_id, marker.last_read_id, marker.unread))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(markers("bob").await, [(direct.id, 0, 1)]);
+    assert_eq!(markers("alice").await, [(direct.id, 0, 0)]);
+    storage
+        .mark_read(direct.id, "bob", secret_id)
+        .await
+        .unwrap();
+    storage.mark_read(direct.id, "bob", 0).await.unwrap();
+    assert_eq!(markers("bob").await, [(direct.id, secret_id, 0)]);
+    storage
+        .mark_read(direct.id, "carol", secret_id)
+        .await
+        .unwrap();
+    assert!(markers("carol").await.is_empty());
+
+    // Input history
+    for input in ["one", "two", "three", "four"] {
+        storage.add_input_history("alice", input, 3).await.unwrap();
+    }
+    storage.add_input_history("bob", "other", 3).await.unwrap();
+    assert_eq!(
+        storage.get_input_history("alice", 10).await.unwrap(),
+        ["two", "three", "four"]
+    );
+    assert_eq!(
+        storage.get_input_history("alice", 2).await.unwrap(),
+        ["three", "four"]
+    );
+    assert_eq!(
+        storage.get_input_history("bob", 10).await.unwrap(),
+        ["other"]
+    );
+}
+
+/// Searches only look at channels the user is a member of.
+async fn check_search(storage: &dyn Storage) {
+    let ops = storage
+        .create_channel("ops", "alice", false, &["alice", "bob"])
+        .await
+        .unwrap();
+    let private = storage
+        .create_channel("alice,carol", "alice", true, &["alice", "carol"])
+        .await
+        .unwrap();
+    let alice = User::authenticated("alice");
+    let bob = User::authenticated("bob");
+    for (content, sender, channel) in [
+        ("Deploying with cargo build --release", &alice, &ops),
+        ("deployment finished", &bob, &ops),
+        ("lunch?", &bob, &ops),
+        ("deploy secrets are in the vault", &alice, &private),
+    ] {
+        let message = Message::new(content.to_string(), sender.clone(), channel.id);
+        storage.insert_message(&message).await.unwrap();
+    }
+
+    let search = |text: &str| MessageSearch {
+        words: MessageSearch::split_words(text),
+        ..Default::default()
+    };
+    let contents = |messages: Vec<Message>| -> Vec<String> {
+        messages.into_iter().map(|m| m.content).collect()
+    };
+
+    let found = storage
+        .search_messages(&search("deploy"), "bob", 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        contents(found),
+        [
+            "deployment finished",
+            "Deploying with cargo build --release"
+        ]
+    );
+    let found = storage
+        .search_messages(&search("deploy"), "alice", 10)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 3);
+    let found = storage
+        .search_messages(&search("cargo release"), "bob", 10)
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    let found = storage
+        .search_messages(&search("\"cargo\" OR NEAR(x"), "bob", 10)
+        .await
+        .unwrap();
+    assert!(found.is_empty());
+
+    let mut from_bob = search("deploy");
+    from_bob.sender = Some("bob".to_string());
+    assert_eq!(
+        contents(
+            storage
+                .search_messages(&from_bob, "alice", 10)
+                .await
+                .unwrap()
+        ),
+        ["deployment finished"]
+    );
+
+    let mut only_private = search("");
+    only_private.channel_id = Some(private.id);
+    assert_eq!(
+        storage
+            .search_messages(&only_private, "alice", 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let mut future = search("deploy");
+    future.after = Some(Utc::now() + Duration::hours(1));
+    assert!(
+        storage
+            .search_messages(&future, "alice", 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let mut past = search("deploy");
+    past.before = Some(Utc::now() + Duration::hours(1));
+    assert_eq!(
+        storage
+            .search_messages(&past, "alice", 10)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+    assert_eq!(
+        storage
+            .search_messages(&past, "alice", 2)
+            .await
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let lunch = storage
+        .search_messages(&search("lunch"), "bob", 10)
+        .await
+        .unwrap();
+    storage.delete_message(lunch[0].id, "bob").await.unwrap();
+    assert!(
+        storage
+            .search_messages(&search("lunch"), "bob", 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let finished = storage
+        .search_messages(&search("finished"), "bob", 10)
+        .await
+        .unwrap();
+    storage
+        .update_message(finished[0].id, "bob", "rollout done", Utc::now())
+        .await
+        .unwrap();
+    assert!(
+        storage
+            .search_messages(&search("finished"), "bob", 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert_eq!(
+        storage
+            .search_messages(&search("rollout"), "bob", 10)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
+
+#[tokio::test]
+async fn memory_storage() {
+    let storage = MemoryStorage::new();
+    check_storage(&storage).await;
+    check_search(&storage).await;
+}
+
+#[tokio::test]
+async fn sqlite_storage_in_memory() {
+    let storage = sqlite_storage().await;
+    check_storage(&storage).await;
+    check_search(&storage).await;
+}
+
+/// Runs against a throwaway Postgres database, e.g.
+/// `SSHLACK_TEST_POSTGRES_URL=postgres://localhost/sshlack_test cargo test --features postgres`.
+/// The database must be empty; it is left migrated and filled afterwards.
+#[cfg(feature = "postgres")]
+#[tokio::test]
+async fn postgres_storage() {
+    let Ok(url) = std::env::var("SSHLACK_TEST_POSTGRES_URL") else {
+        eprintln!("SSHLACK_TEST_POSTGRES_URL is not set, skipping");
+        return;
+    };
+    let storage = sshlack::storage::PostgresStorage::connect(&url)
+        .await
+        .unwrap();
+    storage.migrate().await.unwrap();
+    storage.migrate().await.unwrap();
+    check_storage(&storage).await;
+    check_search(&storage).await;
+}
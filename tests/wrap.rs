@@ -0,0 +1,83 @@
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+
+use sshlack::app::wrap::{break_line, wrap_input, wrap_line};
+
+fn rows(line: Line, width: usize, indent: usize) -> Vec<String> {
+    wrap_line(line, width, indent)
+        .iter()
+        .map(|row| row.to_string())
+        .collect()
+}
+
+#[test]
+fn wraps_between_words_with_an_indent() {
+    assert_eq!(
+        rows(Line::raw("alice: the quick brown fox"), 12, 2),
+        ["alice: the", "  quick", "  brown fox"]
+    );
+    assert_eq!(rows(Line::raw("short"), 12, 2), ["short"]);
+    assert_eq!(rows(Line::raw(""), 12, 2), [""]);
+}
+
+#[test]
+fn breaks_words_longer_than_a_row() {
+    assert_eq!(
+        rows(Line::raw("see https://example.com/a/long/path"), 12, 2),
+        ["see", "  https://ex", "  ample.com/", "  a/long/pat", "  h"]
+    );
+}
+
+#[test]
+fn breaks_code_keeping_whitespace() {
+    assert_eq!(
+        break_line(Line::raw("    let x  = 1;"), 8, 2)
+            .iter()
+            .map(|row| row.to_string())
+            .collect::<Vec<_>>(),
+        ["    let ", "  x  = 1", "  ;"]
+    );
+}
+
+#[test]
+fn measures_wide_graphemes() {
+    // Each of these takes two cells.
+    assert_eq!(
+        rows(Line::raw("日本語のテキスト"), 6, 0),
+        ["日本語", "のテキ", "スト"]
+    );
+    assert_eq!(rows(Line::raw("🎉🎉🎉 👍🏽"), 6, 0), ["🎉🎉🎉", "👍🏽"]);
+}
+
+#[test]
+fn keeps_span_and_line_styles() {
+    let cyan = Style::default().fg(Color::Cyan);
+    let line = Line::from(vec![Span::raw("hi "), Span::styled("@bob and", cyan)])
+        .style(Style::default().fg(Color::Red))
+        .centered();
+    let wrapped = wrap_line(line, 7, 0);
+    assert_eq!(wrapped.len(), 2);
+    assert_eq!(wrapped[0].spans[1].content, "@bob");
+    assert_eq!(wrapped[0].spans[1].style, cyan);
+    assert_eq!(wrapped[1].spans[0].content, "and");
+    assert!(
+        wrapped
+            .iter()
+            .all(|row| row.style.fg == Some(Color::Red) && row.alignment == wrapped[0].alignment)
+    );
+}
+
+#[test]
+fn wraps_input_by_display_width() {
+    let (rows, cursor) = wrap_input("ab日本", "ab日本".len(), 4);
+    assert_eq!(rows, ["ab日", "本"]);
+    assert_eq!(cursor, (1, 1));
+    let (rows, cursor) = wrap_input("abcd", 4, 4);
+    assert_eq!(rows, ["abcd", ""]);
+    assert_eq!(cursor, (1, 0));
+    let (rows, cursor) = wrap_input("ab\ncd", 1, 4);
+    assert_eq!(rows, ["ab", "cd"]);
+    assert_eq!(cursor, (0, 1));
+}